@@ -1,19 +1,43 @@
 // Std
+#[cfg(feature = "regenerate-bindings")]
 use std::env;
 use std::io::Result;
+#[cfg(feature = "regenerate-bindings")]
 use std::path::PathBuf;
 
 // Third Party
+#[cfg(feature = "regenerate-bindings")]
 extern crate bindgen;
 
+/// The library functions the runtime wrapper calls.
+#[cfg(feature = "regenerate-bindings")]
+const FUNCTIONS: &[&str] = &[
+    "init",
+    "close",
+    "load",
+    "unload",
+    "get_model_tensor_info",
+    "free_model_tensor_info",
+    "execute",
+    "tensor_allocate",
+    "tensor_free",
+    "tensor_read",
+    "tensor_write",
+    "allocate_tensor_set",
+    "destroy_tensor_set",
+    "add_tensor_to_tensor_set",
+    "get_tensor_from_tensor_set",
+];
+
+/// Regenerates the bindings from the headers on the include path. Without the
+/// `regenerate-bindings` feature the vendored `src/{name}_bindings.rs` is used instead,
+/// so neither libclang nor the headers are needed to build the crate.
+#[cfg(feature = "regenerate-bindings")]
 fn generate_bindings(name: &str) {
     println!("Called generate_bindings!");
     // Tell cargo to invalidate the built crate whenever the wrapper changes
     println!("cargo:rerun-if-changed={}_wrapper.h", name);
 
-    // Tell cargo to tell rustc to link required system shared libraries
-    println!("cargo:rustc-link-lib=dylib={}", name);
-
     // The bindgen::Builder is the main entry point
     // to bindgen, and lets you build up options for
     // the resulting bindings.
@@ -21,6 +45,13 @@ fn generate_bindings(name: &str) {
         // The input header we would like to generate
         // bindings for.
         .header(format!("{}_wrapper.h", name))
+        // Resolve the library's functions at runtime with libloading instead of
+        // linking against it, so the crate builds and runs without the library.
+        // Only the functions `NrtRuntime` calls are bound, so an older libnrt or a
+        // stub that lacks the rest still loads.
+        .allowlist_function(format!("{}_({})", name, FUNCTIONS.join("|")))
+        .dynamic_library_name(name[..1].to_uppercase() + &name[1..])
+        .dynamic_link_require_all(true)
         // Finish the builder and generate the bindings.
         .generate()
         // Unwrap the Result and panic on failure.
@@ -34,6 +65,7 @@ fn generate_bindings(name: &str) {
 }
 
 fn main() -> Result<()> {
+    #[cfg(feature = "regenerate-bindings")]
     generate_bindings("nrt");
 
     Ok(())
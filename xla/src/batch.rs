@@ -0,0 +1,405 @@
+//! Batching of single requests into the fixed batch dimension a NEFF was compiled for.
+
+// System
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Third party
+use indexmap::IndexMap;
+
+// Local
+use crate::bindings::nrt;
+use crate::error::{NrtError, NrtStatus};
+use crate::executor::spawn_thread;
+use crate::host_tensor::HostTensor;
+use crate::runtime::TensorInfo;
+use crate::xla_runner::{LoadedModel, XLARunResults};
+
+/// How long a [`Batcher`] waits for a batch to fill up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// How long the first request of a batch waits for more requests before the batch
+    /// is run with padding.
+    pub max_wait: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_wait: Duration::from_millis(5),
+        }
+    }
+}
+
+struct Request {
+    inputs: HashMap<String, HostTensor>,
+    reply: Sender<Result<XLARunResults, NrtError>>,
+}
+
+/// Runs single requests on a model compiled for a fixed batch size.
+///
+/// Every input and output of the model must have the batch size as its leading
+/// dimension. A request gives one row of each input, i.e. a value with the shape of
+/// the input without its leading dimension, and gets back one row of each output.
+/// Requests are collected on a worker thread until the batch is full or
+/// [`BatchConfig::max_wait`] has passed since the first of them, packed into the
+/// inputs along the leading dimension, and run at once. The rows a partial batch does
+/// not use are zero-filled, and their outputs are discarded. While a batch runs, up to
+/// a batch worth of requests wait for the next one, and [`Batcher::run`] blocks until
+/// there is room for more.
+///
+/// [`Batcher::run`] takes `&self`, so one batcher can be shared by many threads.
+/// Dropping the batcher waits for the batch in progress and then unloads the model.
+pub struct Batcher {
+    batch_size: usize,
+    /// The info of one row of each input and output.
+    rows: Vec<TensorInfo>,
+    requests: Option<SyncSender<Request>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Batcher {
+    /// Starts batching the runs of `model`. Fails with [`NrtError::NoInputs`] if the
+    /// model has no inputs, with [`NrtError::NotBatched`] if its tensors do not share a
+    /// leading dimension, and with [`NrtError::ThreadSpawn`] if the batching thread
+    /// cannot be started.
+    pub fn new(model: LoadedModel, config: BatchConfig) -> Result<Self, NrtError> {
+        let tensor_info = model.model().tensor_info().to_vec();
+        let first_input = tensor_info
+            .iter()
+            .find(|info| info.usage == nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT)
+            .ok_or_else(|| NrtError::NoInputs {
+                model: model.name().to_string(),
+            })?;
+        let batch_size = first_input.shape.first().copied().unwrap_or(0) as usize;
+        let rows = tensor_info
+            .iter()
+            .map(|info| row_info(info, batch_size))
+            .collect::<Result<_, _>>()?;
+
+        let (requests, received) = mpsc::sync_channel(batch_size);
+        let worker = spawn_thread(format!("xla-batcher-{}", model.name()), move || {
+            serve(model, tensor_info, batch_size, received, config.max_wait)
+        })?;
+        Ok(Batcher {
+            batch_size,
+            rows,
+            requests: Some(requests),
+            worker: Some(worker),
+        })
+    }
+
+    /// The number of requests run at once.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Runs one request as part of a batch and returns its rows of the outputs.
+    ///
+    /// `inputs` must give every input of the model. Invalid inputs are rejected without
+    /// affecting the other requests of the batch. If the batch fails to run, every
+    /// request in it gets the error. The timings are those of the whole batch.
+    pub fn run(&self, inputs: HashMap<String, HostTensor>) -> Result<XLARunResults, NrtError> {
+        check_request(&self.rows, &inputs)?;
+        let (reply, result) = mpsc::channel();
+        let requests = self.requests.as_ref().expect("only taken on drop");
+        // The worker only stops early if it panicked.
+        let stopped = || NrtError::status("batcher", NrtStatus::Failure);
+        requests
+            .send(Request { inputs, reply })
+            .map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for Batcher {
+    fn drop(&mut self) {
+        // The worker runs the requests it already has and exits once it sees the
+        // channel is closed.
+        drop(self.requests.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The info of one row of a batched tensor.
+fn row_info(info: &TensorInfo, batch_size: usize) -> Result<TensorInfo, NrtError> {
+    if batch_size == 0 || info.shape.first() != Some(&(batch_size as u32)) {
+        return Err(NrtError::NotBatched {
+            tensor: info.name.clone(),
+            shape: info.shape.iter().map(|&dim| dim as usize).collect(),
+            batch_size,
+        });
+    }
+    Ok(TensorInfo {
+        size: info.size / batch_size,
+        shape: info.shape[1..].to_vec(),
+        ..info.clone()
+    })
+}
+
+/// Checks that a request gives a row of every input and nothing else.
+fn check_request(
+    rows: &[TensorInfo],
+    inputs: &HashMap<String, HostTensor>,
+) -> Result<(), NrtError> {
+    let input_rows: Vec<&TensorInfo> = rows
+        .iter()
+        .filter(|info| info.usage == nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT)
+        .collect();
+    if let Some(name) = inputs
+        .keys()
+        .find(|name| !input_rows.iter().any(|info| &info.name == *name))
+    {
+        return Err(NrtError::UnknownInput {
+            tensor: name.clone(),
+            expected: input_rows.iter().map(|info| info.name.clone()).collect(),
+        });
+    }
+    for info in input_rows {
+        let value = inputs
+            .get(&info.name)
+            .ok_or_else(|| NrtError::MissingInput {
+                tensor: info.name.clone(),
+            })?;
+        value.check_matches(info)?;
+    }
+    Ok(())
+}
+
+/// Collects requests into batches and runs them until the batcher is dropped.
+fn serve(
+    mut model: LoadedModel,
+    tensor_info: Vec<TensorInfo>,
+    batch_size: usize,
+    requests: Receiver<Request>,
+    max_wait: Duration,
+) {
+    while let Ok(first) = requests.recv() {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + max_wait;
+        while batch.len() < batch_size {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match requests.recv_timeout(wait) {
+                Ok(request) => batch.push(request),
+                Err(_) => break,
+            }
+        }
+
+        let inputs = pack(&tensor_info, batch_size, &batch);
+        match inputs.and_then(|inputs| model.run(inputs)) {
+            Ok(results) => {
+                for (row, request) in batch.into_iter().enumerate() {
+                    let _ = request.reply.send(scatter(&results, batch_size, row));
+                }
+            }
+            Err(e) => {
+                for request in batch {
+                    let _ = request.reply.send(Err(e.clone()));
+                }
+            }
+        }
+    }
+}
+
+/// Stacks the rows of each input of `batch`, padding with zeros.
+fn pack(
+    tensor_info: &[TensorInfo],
+    batch_size: usize,
+    batch: &[Request],
+) -> Result<HashMap<String, HostTensor>, NrtError> {
+    tensor_info
+        .iter()
+        .filter(|info| info.usage == nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT)
+        .map(|info| {
+            let mut tensor = HostTensor::zeros(info)?;
+            let row_size = info.size / batch_size;
+            let bytes = tensor.as_bytes_mut();
+            for (row, request) in batch.iter().enumerate() {
+                bytes[row * row_size..(row + 1) * row_size]
+                    .copy_from_slice(request.inputs[&info.name].as_bytes());
+            }
+            Ok((info.name.clone(), tensor))
+        })
+        .collect()
+}
+
+/// Takes row `row` of each output of a batch.
+fn scatter(
+    results: &XLARunResults,
+    batch_size: usize,
+    row: usize,
+) -> Result<XLARunResults, NrtError> {
+    let output = results
+        .output
+        .iter()
+        .map(|(name, tensor)| {
+            let row_size = tensor.as_bytes().len() / batch_size;
+            let bytes = &tensor.as_bytes()[row * row_size..(row + 1) * row_size];
+            let info = TensorInfo {
+                name: name.clone(),
+                usage: nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                size: row_size,
+                dtype: tensor.dtype(),
+                shape: tensor.shape()[1..].iter().map(|&dim| dim as u32).collect(),
+            };
+            let mut value = HostTensor::output_zeros(&info)?;
+            value.as_bytes_mut().copy_from_slice(bytes);
+            Ok((name.clone(), value))
+        })
+        .collect::<Result<IndexMap<_, _>, NrtError>>()?;
+    Ok(XLARunResults {
+        output,
+        debug_ir: None,
+        runtime: results.runtime,
+        timings: results.timings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Local
+    use super::{BatchConfig, Batcher};
+    use crate::bindings::nrt;
+    use crate::error::{NrtError, NrtStatus};
+    use crate::fake::{FakeKernel, FakeRuntime};
+    use crate::host_tensor::HostTensor;
+    use crate::test_util::{echo_model, f32_tensor, NEFF_PATH};
+    use crate::xla_runner::XLARunner;
+
+    /// A runtime for a model with a batch of 4 rows of 2 values, whose outputs are its
+    /// inputs plus one, and the number of times the model has run.
+    fn plus_one_runtime() -> (Arc<FakeRuntime>, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let kernel_runs = runs.clone();
+        let kernel: FakeKernel = Arc::new(move |inputs, outputs| {
+            kernel_runs.fetch_add(1, Ordering::SeqCst);
+            for (input, output) in inputs[0].chunks(4).zip(outputs[0].chunks_mut(4)) {
+                let value = f32::from_ne_bytes(input.try_into().unwrap());
+                output.copy_from_slice(&(value + 1.0).to_ne_bytes());
+            }
+        });
+        let runtime = FakeRuntime::new(echo_model(vec![4, 2])).with_kernel(kernel);
+        (Arc::new(runtime), runs)
+    }
+
+    fn request(value: f32) -> HashMap<String, HostTensor> {
+        HashMap::from([(
+            "input0".to_string(),
+            HostTensor::from_vec(vec![value, -value]),
+        )])
+    }
+
+    fn batcher(runtime: &Arc<FakeRuntime>, max_wait: Duration) -> Batcher {
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        Batcher::new(runner.load(NEFF_PATH).unwrap(), BatchConfig { max_wait }).unwrap()
+    }
+
+    #[test]
+    fn a_full_batch_runs_at_once_and_each_request_gets_its_row() {
+        let (runtime, runs) = plus_one_runtime();
+        let batcher = batcher(&runtime, Duration::from_secs(60));
+        assert_eq!(batcher.batch_size(), 4);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let batcher = &batcher;
+                    scope.spawn(move || (i, batcher.run(request(i as f32)).unwrap()))
+                })
+                .collect();
+            for handle in handles {
+                let (i, results) = handle.join().unwrap();
+                let i = i as f32;
+                assert_eq!(
+                    results.output["output0"],
+                    HostTensor::from_vec(vec![i + 1.0, 1.0 - i])
+                );
+            }
+        });
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        drop(batcher);
+        assert_eq!(runtime.loaded_model_count(), 0);
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected_on_their_own() {
+        let (runtime, runs) = plus_one_runtime();
+        let batcher = batcher(&runtime, Duration::from_millis(1));
+        assert!(matches!(
+            batcher.run(HashMap::from([(
+                "input0".to_string(),
+                HostTensor::from_vec(vec![1f32; 8])
+            )])),
+            Err(NrtError::ShapeMismatch { .. })
+        ));
+        assert!(matches!(
+            batcher.run(HashMap::new()),
+            Err(NrtError::MissingInput { .. })
+        ));
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn a_lone_request_is_padded_and_gets_the_batch_error() {
+        let (runtime, _) = plus_one_runtime();
+        let batcher = batcher(&runtime, Duration::from_millis(1));
+        let results = batcher.run(request(5.0)).unwrap();
+        assert_eq!(
+            results.output["output0"],
+            HostTensor::from_vec(vec![6.0, -4.0])
+        );
+        runtime.fail_next("nrt_execute", NrtStatus::Timeout);
+        assert_eq!(
+            batcher.run(request(5.0)).unwrap_err().nrt_status(),
+            Some(NrtStatus::Timeout)
+        );
+    }
+
+    #[test]
+    fn tensors_must_share_the_leading_dimension() {
+        let runtime = Arc::new(FakeRuntime::new(vec![
+            f32_tensor(
+                "input0",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
+                vec![4, 2],
+            ),
+            f32_tensor(
+                "output0",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                vec![2, 4],
+            ),
+        ]));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        match Batcher::new(runner.load(NEFF_PATH).unwrap(), BatchConfig::default()) {
+            Err(NrtError::NotBatched { tensor, .. }) => assert_eq!(tensor, "output0"),
+            Err(e) => panic!("Expected output0 not to be batched, got {:?}", e),
+            Ok(_) => panic!("Expected output0 not to be batched"),
+        }
+        assert_eq!(runtime.loaded_model_count(), 0);
+    }
+
+    #[test]
+    fn a_model_without_inputs_cannot_be_batched() {
+        let runtime = Arc::new(FakeRuntime::new(vec![f32_tensor(
+            "output0",
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            vec![4, 2],
+        )]));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        match Batcher::new(runner.load(NEFF_PATH).unwrap(), BatchConfig::default()) {
+            Err(NrtError::NoInputs { model }) => assert_eq!(model, NEFF_PATH),
+            Err(e) => panic!("Expected the model to have no inputs, got {:?}", e),
+            Ok(_) => panic!("Expected the model to have no inputs"),
+        }
+        assert_eq!(runtime.loaded_model_count(), 0);
+    }
+}
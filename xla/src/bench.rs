@@ -0,0 +1,224 @@
+//! Repeated runs of a loaded model, summarized as latency statistics.
+
+// System
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Third party
+use serde::{Deserialize, Serialize};
+
+// Local
+use crate::error::NrtError;
+use crate::host_tensor::HostTensor;
+use crate::xla_runner::{secs_f64, LoadedModel, RunTimings};
+
+/// How many runs [`bench()`] makes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchConfig {
+    /// Runs made before measuring, which are not part of the report.
+    pub warmup: usize,
+    /// The number of measured runs.
+    pub iters: usize,
+    /// If set, measuring also stops once this much time has passed, even if fewer than
+    /// `iters` runs were made. At least one run is always measured.
+    pub duration: Option<Duration>,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            warmup: 10,
+            iters: 100,
+            duration: None,
+        }
+    }
+}
+
+/// Statistics over the durations of one step of the measured runs, as reported in
+/// [`RunTimings`].
+///
+/// Durations are serialized as seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    #[serde(with = "secs_f64")]
+    pub min: Duration,
+    #[serde(with = "secs_f64")]
+    pub mean: Duration,
+    #[serde(with = "secs_f64")]
+    pub p50: Duration,
+    #[serde(with = "secs_f64")]
+    pub p90: Duration,
+    #[serde(with = "secs_f64")]
+    pub p99: Duration,
+    #[serde(with = "secs_f64")]
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Summarizes `samples`, which must not be empty. Percentiles are nearest-rank.
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        samples.sort_unstable();
+        let percentile = |p: usize| {
+            let rank = (p * samples.len()).div_ceil(100).max(1);
+            samples[rank - 1]
+        };
+        LatencyStats {
+            min: samples[0],
+            mean: samples.iter().sum::<Duration>() / samples.len() as u32,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+/// The result of [`bench()`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchReport {
+    /// The name of the benchmarked model.
+    pub model: String,
+    /// The number of warmup runs made.
+    pub warmup: usize,
+    /// The number of measured runs.
+    pub iters: usize,
+    /// [`RunTimings::execute`] of each run.
+    pub execute: LatencyStats,
+    /// Measured runs per second of wall time, including copying inputs and outputs.
+    pub throughput: f64,
+    /// How long each step of loading the model took; see [`LoadedModel::load_timings`].
+    pub load: RunTimings,
+    /// [`RunTimings::input_write`] of each run.
+    pub input_write: LatencyStats,
+    /// [`RunTimings::output_read`] of each run.
+    pub output_read: LatencyStats,
+}
+
+impl BenchReport {
+    /// The report as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a bench report always serializes")
+    }
+}
+
+/// Runs `model` with `inputs` as configured and reports how long the runs took.
+///
+/// Every run is given all of `inputs`; cloning them is not timed. The first failed run
+/// fails the benchmark.
+pub fn bench(
+    model: &mut LoadedModel,
+    inputs: HashMap<String, HostTensor>,
+    config: &BenchConfig,
+) -> Result<BenchReport, NrtError> {
+    for _ in 0..config.warmup {
+        model.run(inputs.clone())?;
+    }
+
+    let mut execute = Vec::with_capacity(config.iters);
+    let mut input_write = Vec::with_capacity(config.iters);
+    let mut output_read = Vec::with_capacity(config.iters);
+    let mut elapsed = Duration::ZERO;
+    let deadline = config.duration.map(|duration| Instant::now() + duration);
+    while execute.is_empty()
+        || (execute.len() < config.iters && deadline.is_none_or(|end| Instant::now() < end))
+    {
+        let inputs = inputs.clone();
+        let start = Instant::now();
+        let timings = model.run(inputs)?.timings;
+        elapsed += start.elapsed();
+        execute.push(timings.execute);
+        input_write.push(timings.input_write);
+        output_read.push(timings.output_read);
+    }
+
+    Ok(BenchReport {
+        model: model.name().to_string(),
+        warmup: config.warmup,
+        iters: execute.len(),
+        throughput: throughput(execute.len(), elapsed),
+        execute: LatencyStats::from_samples(execute),
+        load: model.load_timings(),
+        input_write: LatencyStats::from_samples(input_write),
+        output_read: LatencyStats::from_samples(output_read),
+    })
+}
+
+/// Runs per second. Runs too fast to time count as taking a nanosecond, so that the
+/// throughput is always finite and survives a round trip through JSON.
+fn throughput(runs: usize, elapsed: Duration) -> f64 {
+    runs as f64 / elapsed.max(Duration::from_nanos(1)).as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Local
+    use super::{bench, throughput, BenchConfig, BenchReport, LatencyStats};
+    use crate::fake::{FakeKernel, FakeRuntime};
+    use crate::host_tensor::HostTensor;
+    use crate::test_util::{echo_model, NEFF_PATH};
+    use crate::xla_runner::XLARunner;
+
+    #[test]
+    fn bench_reports_percentiles_over_the_measured_runs() {
+        let millis = |ms: u64| Duration::from_millis(ms);
+        let stats = LatencyStats::from_samples((1..=100).rev().map(millis).collect());
+        assert_eq!(stats.min, millis(1));
+        assert_eq!(stats.p50, millis(50));
+        assert_eq!(stats.p90, millis(90));
+        assert_eq!(stats.p99, millis(99));
+        assert_eq!(stats.max, millis(100));
+        assert_eq!(stats.mean, Duration::from_micros(50_500));
+
+        let kernel: FakeKernel = Arc::new(|inputs, outputs| {
+            std::thread::sleep(Duration::from_millis(1));
+            outputs[0].copy_from_slice(&inputs[0]);
+        });
+        let runtime = Arc::new(FakeRuntime::new(echo_model(vec![1])).with_kernel(kernel));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        let mut model = runner.load(NEFF_PATH).unwrap();
+        let inputs = HashMap::from([("input0".to_string(), HostTensor::from_vec(vec![1f32]))]);
+
+        let config = BenchConfig {
+            warmup: 2,
+            iters: 5,
+            duration: None,
+        };
+        let report = bench(&mut model, inputs.clone(), &config).unwrap();
+        assert_eq!(report.iters, 5);
+        assert!(report.execute.min >= Duration::from_millis(1));
+        assert!(report.execute.min <= report.execute.p50);
+        assert!(report.execute.p99 <= report.execute.max);
+        assert!(report.throughput > 0.0 && report.throughput <= 1000.0);
+        assert_eq!(report.load, model.load_timings());
+
+        let json = report.to_json();
+        let parsed: BenchReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.model, report.model);
+        assert_eq!(parsed.iters, 5);
+        assert_eq!(parsed.load, report.load);
+        // Load timings are in seconds, like the latencies.
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value["load"]["neff_read"].as_f64(),
+            Some(report.load.neff_read.as_secs_f64())
+        );
+
+        // Runs too fast to time still give a finite throughput.
+        assert_eq!(throughput(2, Duration::from_millis(500)), 4.0);
+        assert!(throughput(1, Duration::ZERO).is_finite());
+
+        // A duration cuts the run short, but at least one run is measured.
+        let config = BenchConfig {
+            warmup: 0,
+            iters: 1000,
+            duration: Some(Duration::ZERO),
+        };
+        assert_eq!(bench(&mut model, inputs, &config).unwrap().iters, 1);
+        model.unload().unwrap();
+    }
+}
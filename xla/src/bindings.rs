@@ -4,21 +4,31 @@
 #![allow(deref_nullptr)]
 #![allow(unused)]
 
+/// Bindings to the Neuron runtime (libnrt), resolved at runtime through [`nrt::Nrt`].
+///
+/// The vendored `nrt_bindings.rs` was generated by `build.rs` from the headers of
+/// aws-neuronx-runtime-lib 2.16. To pick up a newer NRT API, build with
+/// `--features regenerate-bindings` and copy `$OUT_DIR/nrt_bindings.rs` over it.
 #[allow(clippy::all)]
 pub mod nrt {
     #![allow(rustdoc::broken_intra_doc_links)]
+    #[cfg(feature = "regenerate-bindings")]
     include!(concat!(env!("OUT_DIR"), "/nrt_bindings.rs"));
+    #[cfg(not(feature = "regenerate-bindings"))]
+    include!("nrt_bindings.rs");
 }
 
 #[cfg(test)]
 mod tests {
     #[test]
+    #[cfg_attr(not(feature = "trn"), ignore = "requires libnrt and a Trainium device")]
     fn nrt_test_bindings_loaded() {
+        let nrt = unsafe { super::nrt::Nrt::new(crate::runtime::DEFAULT_NRT_LIBRARY) }.unwrap();
         unsafe {
-            super::nrt::nrt_init(
+            nrt.nrt_init(
                 super::nrt::nrt_framework_type_t_NRT_FRAMEWORK_TYPE_NO_FW,
-                std::ptr::null() as *const i8,
-                std::ptr::null() as *const i8,
+                std::ptr::null(),
+                std::ptr::null(),
             );
         }
     }
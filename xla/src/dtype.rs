@@ -0,0 +1,183 @@
+//! Host-side values for every `nrt_dtype_t`.
+//!
+//! [`TensorData`] holds the elements of one tensor as a `Vec` of the matching Rust
+//! type. `FLOAT16` and `BFLOAT16` use the [`half`] types, and the fp8 dtypes are
+//! kept as their raw bits since there is no standard Rust type for them.
+
+// Third party
+use half::{bf16, f16};
+
+// Local
+use crate::bindings::nrt;
+use crate::tensor::{as_bytes, as_bytes_mut};
+
+/// The size in bytes of one element of `dtype`, or `None` if the dtype is unknown.
+pub fn dtype_size(dtype: nrt::nrt_dtype_t) -> Option<usize> {
+    match dtype {
+        nrt::nrt_dtype_NRT_DTYPE_INT8
+        | nrt::nrt_dtype_NRT_DTYPE_UINT8
+        | nrt::nrt_dtype_NRT_DTYPE_FP8_E3
+        | nrt::nrt_dtype_NRT_DTYPE_FP8_E4
+        | nrt::nrt_dtype_NRT_DTYPE_FP8_E5 => Some(1),
+        nrt::nrt_dtype_NRT_DTYPE_FLOAT16
+        | nrt::nrt_dtype_NRT_DTYPE_BFLOAT16
+        | nrt::nrt_dtype_NRT_DTYPE_INT16
+        | nrt::nrt_dtype_NRT_DTYPE_UINT16 => Some(2),
+        nrt::nrt_dtype_NRT_DTYPE_FLOAT32
+        | nrt::nrt_dtype_NRT_DTYPE_INT32
+        | nrt::nrt_dtype_NRT_DTYPE_UINT32 => Some(4),
+        nrt::nrt_dtype_NRT_DTYPE_INT64 | nrt::nrt_dtype_NRT_DTYPE_UINT64 => Some(8),
+        _ => None,
+    }
+}
+
+/// The name of `dtype` as it appears in the NRT headers, e.g. `FLOAT32`.
+pub fn dtype_name(dtype: nrt::nrt_dtype_t) -> &'static str {
+    match dtype {
+        nrt::nrt_dtype_NRT_DTYPE_FLOAT32 => "FLOAT32",
+        nrt::nrt_dtype_NRT_DTYPE_FLOAT16 => "FLOAT16",
+        nrt::nrt_dtype_NRT_DTYPE_BFLOAT16 => "BFLOAT16",
+        nrt::nrt_dtype_NRT_DTYPE_INT8 => "INT8",
+        nrt::nrt_dtype_NRT_DTYPE_UINT8 => "UINT8",
+        nrt::nrt_dtype_NRT_DTYPE_INT16 => "INT16",
+        nrt::nrt_dtype_NRT_DTYPE_UINT16 => "UINT16",
+        nrt::nrt_dtype_NRT_DTYPE_INT32 => "INT32",
+        nrt::nrt_dtype_NRT_DTYPE_UINT32 => "UINT32",
+        nrt::nrt_dtype_NRT_DTYPE_INT64 => "INT64",
+        nrt::nrt_dtype_NRT_DTYPE_UINT64 => "UINT64",
+        nrt::nrt_dtype_NRT_DTYPE_FP8_E3 => "FP8_E3",
+        nrt::nrt_dtype_NRT_DTYPE_FP8_E4 => "FP8_E4",
+        nrt::nrt_dtype_NRT_DTYPE_FP8_E5 => "FP8_E5",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Defines `TensorData` with one variant per dtype, and its conversions to raw bytes.
+macro_rules! tensor_data {
+    ($($(#[$doc:meta])* $variant:ident($ty:ty) = $dtype:ident,)*) => {
+        /// The values of a tensor, as a `Vec` of the Rust type of its dtype.
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum TensorData {
+            $($(#[$doc])* $variant(Vec<$ty>),)*
+        }
+
+        impl TensorData {
+            /// `len` zeros of the given dtype.
+            pub fn zeros(dtype: nrt::nrt_dtype_t, len: usize) -> Option<Self> {
+                match dtype {
+                    $(nrt::$dtype => Some(TensorData::$variant(vec![<$ty>::default(); len])),)*
+                    _ => None,
+                }
+            }
+
+            /// The `nrt_dtype_t` of the values.
+            pub fn dtype(&self) -> nrt::nrt_dtype_t {
+                match self {
+                    $(TensorData::$variant(_) => nrt::$dtype,)*
+                }
+            }
+
+            /// The number of elements.
+            pub fn len(&self) -> usize {
+                match self {
+                    $(TensorData::$variant(v) => v.len(),)*
+                }
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.len() == 0
+            }
+
+            /// The values as the bytes stored in a tensor.
+            pub fn as_bytes(&self) -> &[u8] {
+                match self {
+                    $(TensorData::$variant(v) => as_bytes(v),)*
+                }
+            }
+
+            /// The values as a mutable byte buffer to read a tensor into.
+            pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+                match self {
+                    $(TensorData::$variant(v) => as_bytes_mut(v),)*
+                }
+            }
+        }
+    };
+}
+
+tensor_data! {
+    Float32(f32) = nrt_dtype_NRT_DTYPE_FLOAT32,
+    Float16(f16) = nrt_dtype_NRT_DTYPE_FLOAT16,
+    BFloat16(bf16) = nrt_dtype_NRT_DTYPE_BFLOAT16,
+    Int8(i8) = nrt_dtype_NRT_DTYPE_INT8,
+    UInt8(u8) = nrt_dtype_NRT_DTYPE_UINT8,
+    Int16(i16) = nrt_dtype_NRT_DTYPE_INT16,
+    UInt16(u16) = nrt_dtype_NRT_DTYPE_UINT16,
+    Int32(i32) = nrt_dtype_NRT_DTYPE_INT32,
+    UInt32(u32) = nrt_dtype_NRT_DTYPE_UINT32,
+    Int64(i64) = nrt_dtype_NRT_DTYPE_INT64,
+    UInt64(u64) = nrt_dtype_NRT_DTYPE_UINT64,
+    /// The raw bits of fp8 values with a 3 bit exponent.
+    Fp8E3(u8) = nrt_dtype_NRT_DTYPE_FP8_E3,
+    /// The raw bits of fp8 values with a 4 bit exponent.
+    Fp8E4(u8) = nrt_dtype_NRT_DTYPE_FP8_E4,
+    /// The raw bits of fp8 values with a 5 bit exponent.
+    Fp8E5(u8) = nrt_dtype_NRT_DTYPE_FP8_E5,
+}
+
+/// Implements `From<Vec<T>>` for `TensorData` for the element types that map to a
+/// single dtype. Raw fp8 bits must be wrapped in their variant explicitly.
+macro_rules! tensor_data_from_vec {
+    ($($ty:ty => $variant:ident,)*) => {
+        $(
+            impl From<Vec<$ty>> for TensorData {
+                fn from(values: Vec<$ty>) -> Self {
+                    TensorData::$variant(values)
+                }
+            }
+        )*
+    };
+}
+
+tensor_data_from_vec! {
+    f32 => Float32,
+    f16 => Float16,
+    bf16 => BFloat16,
+    i8 => Int8,
+    u8 => UInt8,
+    i16 => Int16,
+    u16 => UInt16,
+    i32 => Int32,
+    u32 => UInt32,
+    i64 => Int64,
+    u64 => UInt64,
+}
+
+#[cfg(test)]
+mod tests {
+    // Third party
+    use half::bf16;
+
+    // Local
+    use super::{dtype_size, TensorData};
+    use crate::bindings::nrt;
+
+    #[test]
+    fn every_dtype_has_matching_values() {
+        for dtype in 0..=nrt::nrt_dtype_NRT_DTYPE_FP8_E5 {
+            match (dtype_size(dtype), TensorData::zeros(dtype, 4)) {
+                (Some(size), Some(data)) => {
+                    assert_eq!(data.dtype(), dtype);
+                    assert_eq!(data.len(), 4);
+                    assert_eq!(data.as_bytes(), vec![0; 4 * size]);
+                }
+                (None, None) => assert_eq!(dtype, nrt::nrt_dtype_NRT_DTYPE_UNKNOWN),
+                (size, data) => panic!("dtype {}: {:?} {:?}", dtype, size, data),
+            }
+        }
+
+        let data = TensorData::from(vec![bf16::from_f32(1.0), bf16::from_f32(-2.0)]);
+        assert_eq!(data.dtype(), nrt::nrt_dtype_NRT_DTYPE_BFLOAT16);
+        assert_eq!(data.as_bytes(), [0x80, 0x3f, 0x00, 0xc0]);
+    }
+}
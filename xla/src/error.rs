@@ -0,0 +1,537 @@
+//! The error type shared by the runtime backends, the `trn` helpers and `XLARunner`.
+
+// System
+use std::borrow::Cow;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Third party
+use serde::{Deserialize, Serialize};
+
+// Local
+use crate::bindings::nrt;
+use crate::dtype::dtype_name;
+use crate::neff::MemoryEstimate;
+
+/// A typed `NRT_STATUS` other than `NRT_SUCCESS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NrtStatus {
+    Failure,
+    Invalid,
+    InvalidHandle,
+    Resource,
+    Timeout,
+    HwError,
+    QueueFull,
+    LoadNotEnoughNc,
+    UnsupportedNeffVersion,
+    FailHostMemAlloc,
+    Uninitialized,
+    Closed,
+    ExecBadInput,
+    ExecCompletedWithNumErr,
+    ExecCompletedWithErr,
+    ExecNcBusy,
+    ExecOob,
+    CollPending,
+    ExecHwErrCollectives,
+    ExecHwErrHbmUe,
+    ExecHwErrNcUe,
+    ExecHwErrDmaAbort,
+    ExecSwNqOverflow,
+    /// A status code that is not in the NRT headers the bindings were generated from.
+    Unknown(nrt::NRT_STATUS),
+}
+
+impl NrtStatus {
+    /// Maps a raw status code to its variant. Returns `None` for `NRT_SUCCESS`.
+    pub fn from_raw(status: nrt::NRT_STATUS) -> Option<Self> {
+        let status = match status {
+            nrt::NRT_STATUS_NRT_SUCCESS => return None,
+            nrt::NRT_STATUS_NRT_FAILURE => NrtStatus::Failure,
+            nrt::NRT_STATUS_NRT_INVALID => NrtStatus::Invalid,
+            nrt::NRT_STATUS_NRT_INVALID_HANDLE => NrtStatus::InvalidHandle,
+            nrt::NRT_STATUS_NRT_RESOURCE => NrtStatus::Resource,
+            nrt::NRT_STATUS_NRT_TIMEOUT => NrtStatus::Timeout,
+            nrt::NRT_STATUS_NRT_HW_ERROR => NrtStatus::HwError,
+            nrt::NRT_STATUS_NRT_QUEUE_FULL => NrtStatus::QueueFull,
+            nrt::NRT_STATUS_NRT_LOAD_NOT_ENOUGH_NC => NrtStatus::LoadNotEnoughNc,
+            nrt::NRT_STATUS_NRT_UNSUPPORTED_NEFF_VERSION => NrtStatus::UnsupportedNeffVersion,
+            nrt::NRT_STATUS_NRT_FAIL_HOST_MEM_ALLOC => NrtStatus::FailHostMemAlloc,
+            nrt::NRT_STATUS_NRT_UNINITIALIZED => NrtStatus::Uninitialized,
+            nrt::NRT_STATUS_NRT_CLOSED => NrtStatus::Closed,
+            nrt::NRT_STATUS_NRT_EXEC_BAD_INPUT => NrtStatus::ExecBadInput,
+            nrt::NRT_STATUS_NRT_EXEC_COMPLETED_WITH_NUM_ERR => NrtStatus::ExecCompletedWithNumErr,
+            nrt::NRT_STATUS_NRT_EXEC_COMPLETED_WITH_ERR => NrtStatus::ExecCompletedWithErr,
+            nrt::NRT_STATUS_NRT_EXEC_NC_BUSY => NrtStatus::ExecNcBusy,
+            nrt::NRT_STATUS_NRT_EXEC_OOB => NrtStatus::ExecOob,
+            nrt::NRT_STATUS_NRT_COLL_PENDING => NrtStatus::CollPending,
+            nrt::NRT_STATUS_NRT_EXEC_HW_ERR_COLLECTIVES => NrtStatus::ExecHwErrCollectives,
+            nrt::NRT_STATUS_NRT_EXEC_HW_ERR_HBM_UE => NrtStatus::ExecHwErrHbmUe,
+            nrt::NRT_STATUS_NRT_EXEC_HW_ERR_NC_UE => NrtStatus::ExecHwErrNcUe,
+            nrt::NRT_STATUS_NRT_EXEC_HW_ERR_DMA_ABORT => NrtStatus::ExecHwErrDmaAbort,
+            nrt::NRT_STATUS_NRT_EXEC_SW_NQ_OVERFLOW => NrtStatus::ExecSwNqOverflow,
+            other => NrtStatus::Unknown(other),
+        };
+        Some(status)
+    }
+
+    /// The raw status code.
+    pub fn raw(self) -> nrt::NRT_STATUS {
+        match self {
+            NrtStatus::Failure => nrt::NRT_STATUS_NRT_FAILURE,
+            NrtStatus::Invalid => nrt::NRT_STATUS_NRT_INVALID,
+            NrtStatus::InvalidHandle => nrt::NRT_STATUS_NRT_INVALID_HANDLE,
+            NrtStatus::Resource => nrt::NRT_STATUS_NRT_RESOURCE,
+            NrtStatus::Timeout => nrt::NRT_STATUS_NRT_TIMEOUT,
+            NrtStatus::HwError => nrt::NRT_STATUS_NRT_HW_ERROR,
+            NrtStatus::QueueFull => nrt::NRT_STATUS_NRT_QUEUE_FULL,
+            NrtStatus::LoadNotEnoughNc => nrt::NRT_STATUS_NRT_LOAD_NOT_ENOUGH_NC,
+            NrtStatus::UnsupportedNeffVersion => nrt::NRT_STATUS_NRT_UNSUPPORTED_NEFF_VERSION,
+            NrtStatus::FailHostMemAlloc => nrt::NRT_STATUS_NRT_FAIL_HOST_MEM_ALLOC,
+            NrtStatus::Uninitialized => nrt::NRT_STATUS_NRT_UNINITIALIZED,
+            NrtStatus::Closed => nrt::NRT_STATUS_NRT_CLOSED,
+            NrtStatus::ExecBadInput => nrt::NRT_STATUS_NRT_EXEC_BAD_INPUT,
+            NrtStatus::ExecCompletedWithNumErr => nrt::NRT_STATUS_NRT_EXEC_COMPLETED_WITH_NUM_ERR,
+            NrtStatus::ExecCompletedWithErr => nrt::NRT_STATUS_NRT_EXEC_COMPLETED_WITH_ERR,
+            NrtStatus::ExecNcBusy => nrt::NRT_STATUS_NRT_EXEC_NC_BUSY,
+            NrtStatus::ExecOob => nrt::NRT_STATUS_NRT_EXEC_OOB,
+            NrtStatus::CollPending => nrt::NRT_STATUS_NRT_COLL_PENDING,
+            NrtStatus::ExecHwErrCollectives => nrt::NRT_STATUS_NRT_EXEC_HW_ERR_COLLECTIVES,
+            NrtStatus::ExecHwErrHbmUe => nrt::NRT_STATUS_NRT_EXEC_HW_ERR_HBM_UE,
+            NrtStatus::ExecHwErrNcUe => nrt::NRT_STATUS_NRT_EXEC_HW_ERR_NC_UE,
+            NrtStatus::ExecHwErrDmaAbort => nrt::NRT_STATUS_NRT_EXEC_HW_ERR_DMA_ABORT,
+            NrtStatus::ExecSwNqOverflow => nrt::NRT_STATUS_NRT_EXEC_SW_NQ_OVERFLOW,
+            NrtStatus::Unknown(status) => status,
+        }
+    }
+
+    /// Whether the same call may succeed if it is retried later, e.g. because the
+    /// NeuronCore was busy or a queue was full. Hardware errors, invalid arguments and
+    /// an uninitialized or closed runtime are not retryable.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            NrtStatus::Timeout
+                | NrtStatus::QueueFull
+                | NrtStatus::ExecNcBusy
+                | NrtStatus::CollPending
+                | NrtStatus::ExecSwNqOverflow
+        )
+    }
+}
+
+impl fmt::Display for NrtStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NrtStatus::Unknown(status) => write!(f, "unknown NRT status {}", status),
+            status => write!(f, "{:?} ({})", status, status.raw()),
+        }
+    }
+}
+
+/// The error returned by everything in this crate that talks to the Neuron runtime.
+///
+/// Errors can be serialized so that a worker process can report them; see
+/// [`crate::isolated`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NrtError {
+    /// An NRT call failed.
+    Status {
+        status: NrtStatus,
+        /// The NRT function that failed, e.g. `nrt_load`.
+        call: Cow<'static, str>,
+        /// The run name of the model the call was made for, if known.
+        model: Option<String>,
+        /// The tensor the call was made for, if any.
+        tensor: Option<String>,
+    },
+    /// libnrt or one of its symbols could not be loaded.
+    Library { path: String, message: String },
+    /// The runtime was closed. NRT cannot be initialized again in the same process.
+    RuntimeClosed,
+    /// A NEFF could not be parsed.
+    InvalidNeff { reason: String },
+    /// A file, such as a NEFF, could not be read or written.
+    Io {
+        operation: IoOperation,
+        path: PathBuf,
+        /// Only the message of the error survives serialization.
+        #[serde(with = "io_error")]
+        source: Arc<std::io::Error>,
+    },
+    /// A tensor's data does not have the size the model expects.
+    SizeMismatch {
+        tensor: String,
+        /// The expected size in bytes.
+        expected: usize,
+        /// The given size in bytes.
+        actual: usize,
+    },
+    /// A different number of input values was given than the model has inputs.
+    InputCountMismatch { expected: usize, actual: usize },
+    /// A tensor has a dtype this crate cannot convert.
+    UnsupportedDtype {
+        tensor: String,
+        dtype: nrt::nrt_dtype_t,
+    },
+    /// The model has an input that was not given a value.
+    MissingInput { tensor: String },
+    /// A value was given for an input the model does not have.
+    UnknownInput {
+        tensor: String,
+        /// The names of the model's inputs.
+        expected: Vec<String>,
+    },
+    /// A buffer was given for an output the model does not have.
+    UnknownOutput {
+        tensor: String,
+        /// The names of the model's outputs.
+        expected: Vec<String>,
+    },
+    /// More than one value was given for the same input.
+    DuplicateInput { tensor: String },
+    /// A shape does not have as many elements as the data it was given for.
+    InvalidShape { shape: Vec<usize>, len: usize },
+    /// A tensor does not have the shape the model expects.
+    ShapeMismatch {
+        tensor: String,
+        expected: Vec<usize>,
+        actual: Vec<usize>,
+    },
+    /// A tensor's data does not have the dtype the model expects.
+    DtypeMismatch {
+        tensor: String,
+        expected: nrt::nrt_dtype_t,
+        actual: nrt::nrt_dtype_t,
+    },
+    /// A model was not loaded because it is estimated to need more device memory than
+    /// the configured budget of the NeuronCores it would be loaded onto.
+    MemoryBudgetExceeded {
+        model: String,
+        estimate: MemoryEstimate,
+        /// The budget of all the NeuronCores in bytes.
+        budget: u64,
+    },
+    /// The worker process running the model died, e.g. from a segfault in libnrt, or was
+    /// killed because it did not respond in time.
+    RuntimeCrashed {
+        /// The signal that killed the worker, if it did not exit by itself.
+        signal: Option<i32>,
+        /// The end of what the worker wrote to stderr.
+        stderr: String,
+    },
+    /// A tensor of a model given to a batcher does not have the batch size as its
+    /// leading dimension.
+    NotBatched {
+        tensor: String,
+        shape: Vec<usize>,
+        batch_size: usize,
+    },
+    /// A model given to a batcher has no inputs, so there are no requests to batch.
+    NoInputs { model: String },
+    /// A thread, such as the worker thread of a NeuronCore, could not be started.
+    ThreadSpawn {
+        thread: String,
+        /// Only the message of the error survives serialization.
+        #[serde(with = "io_error")]
+        source: Arc<std::io::Error>,
+    },
+    /// A worker process of an [`crate::isolated::IsolatedRunner`] tried to start a
+    /// worker of its own, which would start workers without end.
+    NestedWorker,
+}
+
+impl NrtError {
+    /// An error for an NRT call that returned `status`.
+    pub fn status(call: &'static str, status: NrtStatus) -> Self {
+        NrtError::Status {
+            status,
+            call: Cow::Borrowed(call),
+            model: None,
+            tensor: None,
+        }
+    }
+
+    /// Converts the status returned by `call` into a `Result`.
+    pub fn check(call: &'static str, status: nrt::NRT_STATUS) -> Result<(), Self> {
+        match NrtStatus::from_raw(status) {
+            None => Ok(()),
+            Some(status) => Err(Self::status(call, status)),
+        }
+    }
+
+    /// Records the model the failing call was made for.
+    pub fn with_model(mut self, name: &str) -> Self {
+        if let NrtError::Status { ref mut model, .. } = self {
+            model.get_or_insert_with(|| name.to_string());
+        }
+        self
+    }
+
+    /// Records the tensor the failing call was made for.
+    pub fn with_tensor(mut self, name: &str) -> Self {
+        if let NrtError::Status { ref mut tensor, .. } = self {
+            tensor.get_or_insert_with(|| name.to_string());
+        }
+        self
+    }
+
+    /// The NRT status, if this error came from an NRT call.
+    pub fn nrt_status(&self) -> Option<NrtStatus> {
+        match self {
+            NrtError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation may succeed if it is retried. See [`NrtStatus::is_retryable`].
+    pub fn is_retryable(&self) -> bool {
+        self.nrt_status().is_some_and(NrtStatus::is_retryable)
+    }
+}
+
+impl fmt::Display for NrtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NrtError::Status {
+                status,
+                call,
+                model,
+                tensor,
+            } => {
+                write!(f, "{} failed with {}", call, status)?;
+                if let Some(model) = model {
+                    write!(f, " for model {}", model)?;
+                }
+                if let Some(tensor) = tensor {
+                    write!(f, " on tensor {}", tensor)?;
+                }
+                Ok(())
+            }
+            NrtError::Library { path, message } => {
+                write!(f, "Unable to load the NRT library {}: {}", path, message)
+            }
+            NrtError::RuntimeClosed => {
+                write!(
+                    f,
+                    "The Neuron runtime was closed and cannot be initialized again"
+                )
+            }
+            NrtError::InvalidNeff { reason } => write!(f, "Invalid NEFF: {}", reason),
+            NrtError::Io {
+                operation,
+                path,
+                source,
+            } => write!(f, "Unable to {} {}: {}", operation, path.display(), source),
+            NrtError::SizeMismatch {
+                tensor,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Tensor {} expects {} bytes but {} bytes were given",
+                tensor, expected, actual
+            ),
+            NrtError::InputCountMismatch { expected, actual } => write!(
+                f,
+                "The model has {} input tensors but {} values were given",
+                expected, actual
+            ),
+            NrtError::UnsupportedDtype { tensor, dtype } => {
+                write!(f, "Unsupported dtype {} of tensor {}", dtype, tensor)
+            }
+            NrtError::MissingInput { tensor } => write!(f, "No value for input {}", tensor),
+            NrtError::UnknownInput { tensor, expected } => write!(
+                f,
+                "The model has no input named {}; its inputs are {:?}",
+                tensor, expected
+            ),
+            NrtError::UnknownOutput { tensor, expected } => write!(
+                f,
+                "The model has no output named {}; its outputs are {:?}",
+                tensor, expected
+            ),
+            NrtError::DuplicateInput { tensor } => {
+                write!(f, "More than one value for input {}", tensor)
+            }
+            NrtError::InvalidShape { shape, len } => {
+                write!(f, "Shape {:?} does not hold {} elements", shape, len)
+            }
+            NrtError::ShapeMismatch {
+                tensor,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Tensor {} has shape {:?} but was given shape {:?}",
+                tensor, expected, actual
+            ),
+            NrtError::DtypeMismatch {
+                tensor,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Tensor {} has dtype {} but was given {} data",
+                tensor,
+                dtype_name(*expected),
+                dtype_name(*actual)
+            ),
+            NrtError::MemoryBudgetExceeded {
+                model,
+                estimate,
+                budget,
+            } => write!(
+                f,
+                "Model {} needs an estimated {} bytes of device memory ({} of weights, {} of \
+                 inputs and outputs, {} of scratch and {} of instructions) but the budget \
+                 is {} bytes",
+                model,
+                estimate.total(),
+                estimate.weights,
+                estimate.io,
+                estimate.scratch,
+                estimate.instructions,
+                budget
+            ),
+            NrtError::RuntimeCrashed { signal, stderr } => {
+                match signal {
+                    Some(signal) => {
+                        write!(f, "The runtime worker was killed by signal {}", signal)?
+                    }
+                    None => write!(f, "The runtime worker exited unexpectedly")?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, "; its stderr ended with:\n{}", stderr)?;
+                }
+                Ok(())
+            }
+            NrtError::NotBatched {
+                tensor,
+                shape,
+                batch_size,
+            } => write!(
+                f,
+                "Tensor {} has shape {:?}, which does not start with the batch size {}",
+                tensor, shape, batch_size
+            ),
+            NrtError::NoInputs { model } => {
+                write!(f, "Model {} has no inputs to batch requests of", model)
+            }
+            NrtError::ThreadSpawn { thread, source } => {
+                write!(f, "Unable to start thread {}: {}", thread, source)
+            }
+            NrtError::NestedWorker => {
+                write!(
+                    f,
+                    "A runtime worker cannot start a runtime worker of its own"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for NrtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NrtError::Io { source, .. } | NrtError::ThreadSpawn { source, .. } => {
+                Some(source.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What was being done with the file of an [`NrtError::Io`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoOperation {
+    Read,
+    Write,
+    CreateDir,
+    /// Starting the file as a program.
+    Spawn,
+}
+
+impl fmt::Display for IoOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IoOperation::Read => "read",
+            IoOperation::Write => "write",
+            IoOperation::CreateDir => "create the directory",
+            IoOperation::Spawn => "start",
+        })
+    }
+}
+
+/// Serializes an I/O error as its message.
+mod io_error {
+    // System
+    use std::sync::Arc;
+
+    // Third party
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        source: &Arc<std::io::Error>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(source)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<std::io::Error>, D::Error> {
+        let message = String::deserialize(deserializer)?;
+        Ok(Arc::new(std::io::Error::other(message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    // Local
+    use super::{IoOperation, NrtError, NrtStatus};
+    use crate::bindings::nrt;
+
+    #[test]
+    fn nrt_status_round_trips_and_classifies() {
+        assert_eq!(NrtStatus::from_raw(nrt::NRT_STATUS_NRT_SUCCESS), None);
+        for raw in [
+            nrt::NRT_STATUS_NRT_INVALID,
+            nrt::NRT_STATUS_NRT_EXEC_NC_BUSY,
+            nrt::NRT_STATUS_NRT_EXEC_HW_ERR_HBM_UE,
+            4242,
+        ] {
+            assert_eq!(NrtStatus::from_raw(raw).unwrap().raw(), raw);
+        }
+        assert!(NrtStatus::ExecNcBusy.is_retryable());
+        assert!(!NrtStatus::HwError.is_retryable());
+
+        let err = NrtError::check("nrt_tensor_read", nrt::NRT_STATUS_NRT_TIMEOUT)
+            .unwrap_err()
+            .with_model("transformer")
+            .with_tensor("output0");
+        assert!(err.is_retryable());
+        assert_eq!(
+            err.to_string(),
+            "nrt_tensor_read failed with Timeout (5) for model transformer on tensor output0"
+        );
+        let json = serde_json::to_string(&err).unwrap();
+        let parsed: NrtError = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.to_string(), err.to_string());
+
+        let err = NrtError::Io {
+            operation: IoOperation::Write,
+            path: PathBuf::from("/dump/output0.out"),
+            source: Arc::new(std::io::ErrorKind::PermissionDenied.into()),
+        };
+        assert!(err
+            .to_string()
+            .starts_with("Unable to write /dump/output0.out: "));
+    }
+}
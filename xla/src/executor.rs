@@ -0,0 +1,560 @@
+//! Worker threads that run models off the calling thread, one per NeuronCore, for
+//! [`crate::xla_runner::LoadedModel::run_async`].
+//!
+//! Nothing here depends on an async runtime: [`RunFuture`] can be awaited on any
+//! executor, and is woken from the worker thread when its run finishes.
+
+// System
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+
+// Local
+use crate::error::{NrtError, NrtStatus};
+use crate::xla_runner::XLARunResults;
+
+/// How many runs can be queued on each NeuronCore unless
+/// [`crate::xla_runner::XLARunner::set_queue_depth`] says otherwise.
+pub const DEFAULT_QUEUE_DEPTH: usize = 8;
+
+type RunResult = Result<XLARunResults, NrtError>;
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The worker threads of one [`crate::shared::SharedRuntime`], started the first time a
+/// run is queued on their NeuronCore, so that every runner on the runtime shares the
+/// bound of each core's queue. The threads exit once every runner and model on the
+/// runtime is dropped and their queued runs have finished.
+pub(crate) struct Executors {
+    /// The depth of every queue.
+    queue_depth: AtomicUsize,
+    cores: Mutex<HashMap<i32, Arc<CoreQueue>>>,
+}
+
+impl Executors {
+    pub(crate) fn new(queue_depth: usize) -> Self {
+        Executors {
+            queue_depth: AtomicUsize::new(queue_depth.max(1)),
+            cores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Changes the depth of every queue, including the queues already started. A depth
+    /// of 0 is treated as 1.
+    pub(crate) fn set_queue_depth(&self, depth: usize) {
+        let depth = depth.max(1);
+        let cores = lock(&self.cores);
+        self.queue_depth.store(depth, Ordering::Relaxed);
+        for queue in cores.values() {
+            let waiters = {
+                let mut state = lock(&queue.state);
+                state.depth = depth;
+                state.waiters_with_room()
+            };
+            for waker in waiters {
+                waker.wake();
+            }
+        }
+    }
+
+    /// The queue of the worker thread for `logical_nc_id`, starting it if needed.
+    fn core(&self, logical_nc_id: i32) -> Result<Arc<CoreQueue>, NrtError> {
+        let mut cores = lock(&self.cores);
+        if let Some(queue) = cores.get(&logical_nc_id) {
+            return Ok(queue.clone());
+        }
+        let queue = Arc::new(CoreQueue {
+            state: Mutex::new(QueueState {
+                depth: self.queue_depth.load(Ordering::Relaxed),
+                ..QueueState::default()
+            }),
+            job_queued: Condvar::new(),
+        });
+        let worker = queue.clone();
+        spawn_thread(format!("xla-nc{}", logical_nc_id), move || worker.work())?;
+        cores.insert(logical_nc_id, queue.clone());
+        Ok(queue)
+    }
+
+    /// Queues `run` on the worker thread for `logical_nc_id`. The run is only queued
+    /// once the returned future is first polled with room in the queue.
+    pub(crate) fn submit<F>(&self, logical_nc_id: i32, run: F) -> Result<RunFuture, NrtError>
+    where
+        F: FnOnce() -> RunResult + Send + 'static,
+    {
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let completion = Completion { slot: slot.clone() };
+        Ok(RunFuture {
+            queue: self.core(logical_nc_id)?,
+            job: Some(Box::new(move || completion.complete(run()))),
+            waiter: None,
+            slot,
+        })
+    }
+}
+
+impl Drop for Executors {
+    fn drop(&mut self) {
+        for queue in lock(&self.cores).values() {
+            let waiting = {
+                let mut state = lock(&queue.state);
+                state.closed = true;
+                std::mem::take(&mut state.waiting)
+            };
+            queue.job_queued.notify_one();
+            // The waiting futures can never be queued now, so they fail.
+            for (_, waker) in waiting {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Starts a thread named `name`.
+pub(crate) fn spawn_thread<F, T>(name: String, f: F) -> Result<JoinHandle<T>, NrtError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    std::thread::Builder::new()
+        .name(name.clone())
+        .spawn(f)
+        .map_err(|e| NrtError::ThreadSpawn {
+            thread: name,
+            source: Arc::new(e),
+        })
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// How many jobs the queue holds.
+    depth: usize,
+    jobs: VecDeque<Job>,
+    /// The futures waiting for room in the queue, in the order they found it full, by
+    /// their waiter id. A future keeps its place until it is queued, and room goes to
+    /// the futures at the front first.
+    waiting: VecDeque<(u64, Waker)>,
+    /// The last waiter id handed out.
+    last_waiter: u64,
+    closed: bool,
+}
+
+impl QueueState {
+    /// How many more jobs fit in the queue.
+    fn room(&self) -> usize {
+        self.depth.saturating_sub(self.jobs.len())
+    }
+
+    /// The waker of the last waiting future there is room for, which is the one that
+    /// a single new slot in the queue goes to.
+    fn last_waiter_with_room(&self) -> Option<Waker> {
+        let room = self.room();
+        let (_, waker) = self.waiting.get(room.checked_sub(1)?)?;
+        Some(waker.clone())
+    }
+
+    /// The wakers of every waiting future there is room for.
+    fn waiters_with_room(&self) -> Vec<Waker> {
+        self.waiting
+            .iter()
+            .take(self.room())
+            .map(|(_, waker)| waker.clone())
+            .collect()
+    }
+}
+
+/// What became of a job [`CoreQueue::try_push`] was given.
+enum Push {
+    Queued,
+    /// The queue is full; the job is given back.
+    Full(Job),
+    /// The worker has stopped, so the job can never run.
+    Closed,
+}
+
+/// The bounded queue of one worker thread.
+struct CoreQueue {
+    state: Mutex<QueueState>,
+    job_queued: Condvar,
+}
+
+impl CoreQueue {
+    /// Queues `job`, or gives it back and wakes `waker` once there is room for it.
+    /// `waiter` identifies the future in the queue's list of waiting futures.
+    fn try_push(&self, job: Job, waiter: &mut Option<u64>, waker: &Waker) -> Push {
+        let mut state = lock(&self.state);
+        if state.closed {
+            // The worker stops once the models and the runners are dropped.
+            return Push::Closed;
+        }
+        let position = waiter.and_then(|id| state.waiting.iter().position(|(w, _)| *w == id));
+        // Futures that have waited longer go first, even if they have been woken but not
+        // polled yet.
+        if position.unwrap_or(state.waiting.len()) >= state.room() {
+            match position {
+                Some(position) => state.waiting[position].1.clone_from(waker),
+                None => {
+                    let id = match *waiter {
+                        Some(id) => id,
+                        None => {
+                            state.last_waiter += 1;
+                            state.last_waiter
+                        }
+                    };
+                    *waiter = Some(id);
+                    state.waiting.push_back((id, waker.clone()));
+                }
+            }
+            return Push::Full(job);
+        }
+        if let Some(position) = position {
+            state.waiting.remove(position);
+        }
+        *waiter = None;
+        state.jobs.push_back(job);
+        self.job_queued.notify_one();
+        Push::Queued
+    }
+
+    fn work(&self) {
+        loop {
+            let (job, waiter) = {
+                let state = lock(&self.state);
+                let mut state = self
+                    .job_queued
+                    .wait_while(state, |state| state.jobs.is_empty() && !state.closed)
+                    .unwrap_or_else(|e| e.into_inner());
+                let Some(job) = state.jobs.pop_front() else {
+                    return;
+                };
+                // There is room for one more run now.
+                (job, state.last_waiter_with_room())
+            };
+            if let Some(waker) = waiter {
+                waker.wake();
+            }
+            // A panicking run completes its future with an error as it unwinds, and
+            // must not take the runs queued after it down with the thread.
+            let _ = std::panic::catch_unwind(AssertUnwindSafe(job));
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    result: Option<RunResult>,
+    waker: Option<Waker>,
+}
+
+/// Delivers the result of a run to its future, or an error if the run panicked.
+struct Completion {
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl Completion {
+    fn complete(self, result: RunResult) {
+        lock(&self.slot).result = Some(result);
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        let mut slot = lock(&self.slot);
+        if slot.result.is_none() {
+            slot.result = Some(Err(NrtError::status("run_async", NrtStatus::Failure)));
+        }
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The result of a run queued with [`crate::xla_runner::LoadedModel::run_async`].
+///
+/// The run is queued the first time the future is polled. While the queue of the
+/// model's NeuronCore is full the future stays pending, so callers that submit faster
+/// than the device runs are held back instead of queueing without bound. Dropping the
+/// future before it is queued cancels the run; dropping it afterwards does not. A future
+/// that is first polled after the model and every runner on its runtime were dropped
+/// fails with [`NrtStatus::Closed`].
+pub struct RunFuture {
+    queue: Arc<CoreQueue>,
+    /// The run, until it is queued.
+    job: Option<Job>,
+    /// The id of the future among those waiting for room in the queue, once it has had
+    /// to wait.
+    waiter: Option<u64>,
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl Future for RunFuture {
+    type Output = RunResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RunResult> {
+        let this = &mut *self;
+        if let Some(job) = this.job.take() {
+            match this.queue.try_push(job, &mut this.waiter, cx.waker()) {
+                Push::Queued => {}
+                Push::Full(job) => {
+                    this.job = Some(job);
+                    return Poll::Pending;
+                }
+                Push::Closed => {
+                    return Poll::Ready(Err(NrtError::status("run_async", NrtStatus::Closed)))
+                }
+            }
+        }
+        let mut slot = lock(&self.slot);
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for RunFuture {
+    fn drop(&mut self) {
+        if self.job.is_none() {
+            return;
+        }
+        // A cancelled future may have been woken for room it will now never take, so
+        // the next waiting future is woken in its place.
+        let waiter = {
+            let mut state = lock(&self.queue.state);
+            let position = self
+                .waiter
+                .and_then(|id| state.waiting.iter().position(|(w, _)| *w == id));
+            match position {
+                Some(position) if position < state.room() => {
+                    state.waiting.remove(position);
+                    state.last_waiter_with_room()
+                }
+                Some(position) => {
+                    state.waiting.remove(position);
+                    None
+                }
+                None => None,
+            }
+        };
+        if let Some(waker) = waiter {
+            waker.wake();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Runs execute outside of these locks, so a panic cannot leave them inconsistent.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    // Local
+    use super::{lock, CoreQueue, Job, Push, QueueState};
+    use crate::error::{NrtError, NrtStatus};
+    use crate::fake::{FakeKernel, FakeRuntime};
+    use crate::host_tensor::HostTensor;
+    use crate::shared::SharedRuntime;
+    use crate::test_util::{echo_model, NEFF_PATH};
+    use crate::xla_runner::XLARunner;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    #[test]
+    fn run_async_queues_runs_on_the_core_worker() {
+        // Runs whose input is negative block until the gate is opened.
+        let gate = Arc::new((Mutex::new(false), Condvar::new()));
+        let blocked = Arc::new(AtomicBool::new(false));
+        let (kernel_gate, kernel_blocked) = (gate.clone(), blocked.clone());
+        let kernel: FakeKernel = Arc::new(move |inputs, outputs| {
+            if f32::from_ne_bytes(inputs[0][..4].try_into().unwrap()) < 0.0 {
+                kernel_blocked.store(true, Ordering::SeqCst);
+                let (open, opened) = &*kernel_gate;
+                let _open = opened
+                    .wait_while(open.lock().unwrap(), |open| !*open)
+                    .unwrap();
+            }
+            outputs[0].copy_from_slice(&inputs[0]);
+        });
+        let runtime = Arc::new(FakeRuntime::new(echo_model(vec![1])).with_kernel(kernel));
+        let mut runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        runner.set_queue_depth(1);
+        let model = runner.load(NEFF_PATH).unwrap();
+        let inputs =
+            |value: f32| HashMap::from([("input0".to_string(), HostTensor::from_vec(vec![value]))]);
+        let set_gate = |value: bool| {
+            *gate.0.lock().unwrap() = value;
+            gate.1.notify_all();
+        };
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        // The first run blocks the worker, the second fills the queue, and the third
+        // has to wait for room without blocking this thread.
+        let mut first = model.run_async(inputs(-1.0)).unwrap();
+        assert!(Pin::new(&mut first).poll(&mut cx).is_pending());
+        while !blocked.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let mut second = model.run_async(inputs(2.0)).unwrap();
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        assert!(second.job.is_none());
+        let mut third = model.run_async(inputs(3.0)).unwrap();
+        assert!(Pin::new(&mut third).poll(&mut cx).is_pending());
+        assert!(third.job.is_some());
+        assert_eq!(lock(&third.queue.state).waiting.len(), 1);
+
+        set_gate(true);
+        for (future, value) in [(first, -1.0), (second, 2.0), (third, 3.0)] {
+            let results = block_on(future).unwrap();
+            assert_eq!(results.output["output0"], HostTensor::from_vec(vec![value]));
+        }
+
+        // Dropping the model lets the run in progress finish, fails the queued one, and
+        // then unloads the model.
+        set_gate(false);
+        blocked.store(false, Ordering::SeqCst);
+        let mut running = model.run_async(inputs(-1.0)).unwrap();
+        assert!(Pin::new(&mut running).poll(&mut cx).is_pending());
+        while !blocked.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let mut queued = model.run_async(inputs(1.0)).unwrap();
+        assert!(Pin::new(&mut queued).poll(&mut cx).is_pending());
+        drop(model);
+        assert_eq!(runtime.loaded_model_count(), 1);
+        set_gate(true);
+        assert!(block_on(running).is_ok());
+        match block_on(queued) {
+            Err(NrtError::Status { status, .. }) => assert_eq!(status, NrtStatus::InvalidHandle),
+            other => panic!("Expected the queued run to fail, got {:?}", other),
+        }
+        assert_eq!(runtime.loaded_model_count(), 0);
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+
+        // Unloading waits for the run in progress to let go of the model.
+        set_gate(false);
+        blocked.store(false, Ordering::SeqCst);
+        let model = runner.load(NEFF_PATH).unwrap();
+        let mut running = model.run_async(inputs(-1.0)).unwrap();
+        assert!(Pin::new(&mut running).poll(&mut cx).is_pending());
+        while !blocked.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let opener_gate = gate.clone();
+        let opener = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            *opener_gate.0.lock().unwrap() = true;
+            opener_gate.1.notify_all();
+        });
+        model.unload().unwrap();
+        opener.join().unwrap();
+        assert!(block_on(running).is_ok());
+        assert_eq!(runtime.loaded_model_count(), 0);
+
+        // A run that is first polled once the model and its runner are gone fails.
+        let model = runner.load(NEFF_PATH).unwrap();
+        let pending = model.run_async(inputs(1.0)).unwrap();
+        drop(model);
+        drop(runner);
+        match block_on(pending) {
+            Err(NrtError::Status { status, .. }) => assert_eq!(status, NrtStatus::Closed),
+            other => panic!("Expected the run to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn woken_waiters_keep_their_place_in_line() {
+        let queue = CoreQueue {
+            state: Mutex::new(QueueState {
+                depth: 1,
+                ..QueueState::default()
+            }),
+            job_queued: Condvar::new(),
+        };
+        let job = || -> Job { Box::new(|| {}) };
+        let waker = Waker::noop();
+        let (mut first, mut second, mut third) = (None, None, None);
+        assert!(matches!(
+            queue.try_push(job(), &mut first, waker),
+            Push::Queued
+        ));
+        assert!(matches!(
+            queue.try_push(job(), &mut second, waker),
+            Push::Full(_)
+        ));
+
+        // The worker takes the first job, and the room goes to the second future even
+        // though the third one asks for it before the second is polled again.
+        lock(&queue.state).jobs.pop_front();
+        assert!(matches!(
+            queue.try_push(job(), &mut third, waker),
+            Push::Full(_)
+        ));
+        assert!(matches!(
+            queue.try_push(job(), &mut second, waker),
+            Push::Queued
+        ));
+        let waiting: Vec<_> = lock(&queue.state)
+            .waiting
+            .iter()
+            .map(|(id, _)| *id)
+            .collect();
+        assert_eq!(waiting, [third.unwrap()]);
+    }
+
+    #[test]
+    fn runners_on_one_runtime_share_the_core_queues() {
+        let shared = SharedRuntime::new(Arc::new(FakeRuntime::new(echo_model(vec![1]))));
+        let mut first = XLARunner::with_shared_runtime(&shared).unwrap();
+        let second = XLARunner::with_shared_runtime(&shared).unwrap();
+        first.set_queue_depth(1);
+        let inputs =
+            |value: f32| HashMap::from([("input0".to_string(), HostTensor::from_vec(vec![value]))]);
+
+        let first_model = first.load(NEFF_PATH).unwrap();
+        let second_model = second.load(NEFF_PATH).unwrap();
+        let first_run = first_model.run_async(inputs(1.0)).unwrap();
+        let second_run = second_model.run_async(inputs(2.0)).unwrap();
+        assert!(Arc::ptr_eq(&first_run.queue, &second_run.queue));
+        assert_eq!(lock(&second_run.queue.state).depth, 1);
+        for (future, value) in [(first_run, 1.0), (second_run, 2.0)] {
+            let results = block_on(future).unwrap();
+            assert_eq!(results.output["output0"], HostTensor::from_vec(vec![value]));
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! An in-process stand-in for the Neuron runtime.
+//!
+//! [`FakeRuntime`] implements [`NeuronRuntime`] entirely in host memory. Every NEFF it
+//! is asked to load is given the tensor signature the fake was built with, and
+//! `execute` fills the output tensors deterministically from the inputs. This lets
+//! the runner be tested on machines without libnrt or a Trainium device.
+
+// System
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Local
+use crate::bindings::nrt;
+use crate::error::{NrtError, NrtStatus};
+use crate::model::LoadOptions;
+use crate::runtime::{ModelHandle, NeuronRuntime, TensorHandle, TensorInfo, TensorSetHandle};
+
+/// Computes the output tensors of a fake model from its input tensors.
+///
+/// Inputs and outputs are passed in the order they appear in the model's tensor info.
+pub type FakeKernel = Arc<dyn Fn(&[Vec<u8>], &mut [Vec<u8>]) + Send + Sync>;
+
+#[derive(Default)]
+struct FakeState {
+    initialized: bool,
+    closed: bool,
+    next_handle: usize,
+    models: HashMap<usize, FakeModel>,
+    /// The NeuronCore each tensor was allocated on.
+    tensor_ncs: HashMap<usize, i32>,
+    tensors: HashMap<usize, Vec<u8>>,
+    tensor_sets: HashMap<usize, HashMap<String, usize>>,
+    /// Failures to return from a later call to the given NRT function, with the number
+    /// of calls to let through first.
+    faults: HashMap<&'static str, (usize, NrtStatus)>,
+    /// NEFFs that crash the process when they are loaded.
+    crashing_neffs: Vec<Vec<u8>>,
+    /// Input bytes that crash the process when a model is executed with them.
+    crashing_inputs: Vec<Vec<u8>>,
+}
+
+struct FakeModel {
+    tensor_info: Vec<TensorInfo>,
+    options: LoadOptions,
+}
+
+impl FakeState {
+    fn next_handle(&mut self) -> usize {
+        self.next_handle += 1;
+        self.next_handle
+    }
+
+    /// The failure armed for this call to `call`, if any.
+    fn take_fault(&mut self, call: &'static str) -> Option<NrtStatus> {
+        let (skip, status) = self.faults.get_mut(call)?;
+        if *skip > 0 {
+            *skip -= 1;
+            return None;
+        }
+        let status = *status;
+        self.faults.remove(call);
+        Some(status)
+    }
+}
+
+/// A [`NeuronRuntime`] that keeps models and tensors in host memory.
+pub struct FakeRuntime {
+    tensor_info: Vec<TensorInfo>,
+    kernel: FakeKernel,
+    /// The number of NeuronCores models and tensors can be placed on, if limited.
+    nc_count: Option<i32>,
+    state: Mutex<FakeState>,
+}
+
+impl FakeRuntime {
+    /// Creates a fake whose models all have the given tensor signature.
+    ///
+    /// Unless a kernel is set with [`FakeRuntime::with_kernel`], every output tensor is
+    /// filled by repeating the bytes of all input tensors, or with a fixed byte pattern
+    /// when the model has no inputs.
+    pub fn new(tensor_info: Vec<TensorInfo>) -> Self {
+        FakeRuntime {
+            tensor_info,
+            kernel: Arc::new(default_kernel),
+            nc_count: None,
+            state: Mutex::new(FakeState::default()),
+        }
+    }
+
+    /// Simulates a device with `nc_count` NeuronCores. Loading a model that does not fit
+    /// on them fails with `LoadNotEnoughNc`, and allocating a tensor on a core that does
+    /// not exist fails with `Invalid`. There is no limit by default.
+    pub fn with_nc_count(mut self, nc_count: i32) -> Self {
+        self.nc_count = Some(nc_count);
+        self
+    }
+
+    /// Replaces the function used to compute outputs in `execute`.
+    pub fn with_kernel(mut self, kernel: FakeKernel) -> Self {
+        self.kernel = kernel;
+        self
+    }
+
+    /// Makes the next call to the NRT function `call`, e.g. `nrt_execute`, fail with
+    /// `status`. Only calls that return a status can fail.
+    pub fn fail_next(&self, call: &'static str, status: NrtStatus) {
+        self.fail_after(call, 0, status);
+    }
+
+    /// Like [`FakeRuntime::fail_next`], but lets `calls` calls to `call` succeed first.
+    pub fn fail_after(&self, call: &'static str, calls: usize, status: NrtStatus) {
+        self.state().faults.insert(call, (calls, status));
+    }
+
+    /// Makes loading exactly the bytes `neff` kill the process with SIGSEGV, like libnrt
+    /// does for the large transformer. Only useful in a worker process; see
+    /// [`crate::isolated`].
+    pub fn crash_on_load(&self, neff: Vec<u8>) {
+        self.state().crashing_neffs.push(neff);
+    }
+
+    /// Makes executing a model whose input tensors, concatenated, hold exactly the bytes
+    /// `inputs` kill the process with SIGSEGV. Only useful in a worker process; see
+    /// [`crate::isolated`].
+    pub fn crash_on_execute(&self, inputs: Vec<u8>) {
+        self.state().crashing_inputs.push(inputs);
+    }
+
+    /// Whether `init` has been called and `close` has not.
+    pub fn is_initialized(&self) -> bool {
+        self.state().initialized
+    }
+
+    /// The number of models that are currently loaded.
+    pub fn loaded_model_count(&self) -> usize {
+        self.state().models.len()
+    }
+
+    /// The NeuronCores of every loaded model, in the order they were loaded.
+    pub fn loaded_model_placements(&self) -> Vec<LoadOptions> {
+        let state = self.state();
+        let mut models: Vec<_> = state.models.iter().collect();
+        models.sort_by_key(|(handle, _)| **handle);
+        models.into_iter().map(|(_, model)| model.options).collect()
+    }
+
+    /// The NeuronCore of every allocated tensor, in the order they were allocated.
+    pub fn allocated_tensor_ncs(&self) -> Vec<i32> {
+        let state = self.state();
+        let mut tensors: Vec<_> = state.tensor_ncs.iter().collect();
+        tensors.sort_by_key(|(handle, _)| **handle);
+        tensors.into_iter().map(|(_, &nc)| nc).collect()
+    }
+
+    /// The number of tensors that are currently allocated.
+    pub fn allocated_tensor_count(&self) -> usize {
+        self.state().tensors.len()
+    }
+
+    /// The number of tensor sets that are currently allocated.
+    pub fn allocated_tensor_set_count(&self) -> usize {
+        self.state().tensor_sets.len()
+    }
+
+    fn state(&self) -> MutexGuard<'_, FakeState> {
+        // A panic while the lock is held cannot leave the maps half updated.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn initialized_state(&self, call: &'static str) -> Result<MutexGuard<'_, FakeState>, NrtError> {
+        let mut state = self.state();
+        if let Some(status) = state.take_fault(call) {
+            return Err(NrtError::status(call, status));
+        }
+        if !state.initialized {
+            return Err(NrtError::status(call, NrtStatus::Uninitialized));
+        }
+        Ok(state)
+    }
+}
+
+/// Kills the process with SIGSEGV. The default handler is restored first, since the
+/// Rust runtime handles SIGSEGV itself to report stack overflows.
+fn segfault() -> ! {
+    unsafe {
+        libc::signal(libc::SIGSEGV, libc::SIG_DFL);
+        libc::raise(libc::SIGSEGV);
+    }
+    unreachable!("SIGSEGV did not kill the process")
+}
+
+fn default_kernel(inputs: &[Vec<u8>], outputs: &mut [Vec<u8>]) {
+    let input_bytes: Vec<u8> = inputs.concat();
+    for output in outputs.iter_mut() {
+        for (i, byte) in output.iter_mut().enumerate() {
+            *byte = if input_bytes.is_empty() {
+                (i % 251) as u8
+            } else {
+                input_bytes[i % input_bytes.len()]
+            };
+        }
+    }
+}
+
+impl NeuronRuntime for FakeRuntime {
+    fn init(&self) -> Result<(), NrtError> {
+        let mut state = self.state();
+        if let Some(status) = state.take_fault("nrt_init") {
+            return Err(NrtError::status("nrt_init", status));
+        }
+        // Like libnrt, the fake can only be initialized once per process.
+        if state.initialized || state.closed {
+            return Err(NrtError::status("nrt_init", NrtStatus::Failure));
+        }
+        state.initialized = true;
+        Ok(())
+    }
+
+    fn close(&self) {
+        let mut state = self.state();
+        state.initialized = false;
+        state.closed = true;
+    }
+
+    fn load(&self, neff: &[u8], start_nc: i32, nc_count: i32) -> Result<ModelHandle, NrtError> {
+        let mut state = self.initialized_state("nrt_load")?;
+        if neff.is_empty() || start_nc < 0 || nc_count < 1 {
+            return Err(NrtError::status("nrt_load", NrtStatus::Invalid));
+        }
+        if self
+            .nc_count
+            .is_some_and(|limit| start_nc.checked_add(nc_count).is_none_or(|end| end > limit))
+        {
+            return Err(NrtError::status("nrt_load", NrtStatus::LoadNotEnoughNc));
+        }
+        if state.crashing_neffs.iter().any(|crashing| crashing == neff) {
+            segfault();
+        }
+        let handle = state.next_handle();
+        let model = FakeModel {
+            tensor_info: self.tensor_info.clone(),
+            options: LoadOptions { start_nc, nc_count },
+        };
+        state.models.insert(handle, model);
+        Ok(ModelHandle(handle))
+    }
+
+    fn unload(&self, model: ModelHandle) -> Result<(), NrtError> {
+        let mut state = self.initialized_state("nrt_unload")?;
+        state
+            .models
+            .remove(&model.0)
+            .map(|_| ())
+            .ok_or_else(|| NrtError::status("nrt_unload", NrtStatus::InvalidHandle))
+    }
+
+    fn model_tensor_info(&self, model: ModelHandle) -> Result<Vec<TensorInfo>, NrtError> {
+        let state = self.initialized_state("nrt_get_model_tensor_info")?;
+        state
+            .models
+            .get(&model.0)
+            .map(|model| model.tensor_info.clone())
+            .ok_or_else(|| NrtError::status("nrt_get_model_tensor_info", NrtStatus::InvalidHandle))
+    }
+
+    fn tensor_allocate(
+        &self,
+        _placement: nrt::nrt_tensor_placement_t,
+        logical_nc_id: i32,
+        size: usize,
+        _name: &str,
+    ) -> Result<TensorHandle, NrtError> {
+        let mut state = self.initialized_state("nrt_tensor_allocate")?;
+        if logical_nc_id < 0 || self.nc_count.is_some_and(|limit| logical_nc_id >= limit) {
+            return Err(NrtError::status("nrt_tensor_allocate", NrtStatus::Invalid));
+        }
+        let handle = state.next_handle();
+        state.tensors.insert(handle, vec![0; size]);
+        state.tensor_ncs.insert(handle, logical_nc_id);
+        Ok(TensorHandle(handle))
+    }
+
+    fn tensor_free(&self, tensor: TensorHandle) {
+        let mut state = self.state();
+        state.tensors.remove(&tensor.0);
+        state.tensor_ncs.remove(&tensor.0);
+    }
+
+    fn tensor_read(
+        &self,
+        tensor: TensorHandle,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> Result<(), NrtError> {
+        let state = self.initialized_state("nrt_tensor_read")?;
+        let data = state
+            .tensors
+            .get(&tensor.0)
+            .ok_or_else(|| NrtError::status("nrt_tensor_read", NrtStatus::InvalidHandle))?;
+        let src = offset
+            .checked_add(buf.len())
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| NrtError::status("nrt_tensor_read", NrtStatus::Invalid))?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn tensor_write(
+        &self,
+        tensor: TensorHandle,
+        buf: &[u8],
+        offset: usize,
+    ) -> Result<(), NrtError> {
+        let mut state = self.initialized_state("nrt_tensor_write")?;
+        let data = state
+            .tensors
+            .get_mut(&tensor.0)
+            .ok_or_else(|| NrtError::status("nrt_tensor_write", NrtStatus::InvalidHandle))?;
+        let dst = offset
+            .checked_add(buf.len())
+            .and_then(|end| data.get_mut(offset..end))
+            .ok_or_else(|| NrtError::status("nrt_tensor_write", NrtStatus::Invalid))?;
+        dst.copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn allocate_tensor_set(&self) -> Result<TensorSetHandle, NrtError> {
+        let mut state = self.initialized_state("nrt_allocate_tensor_set")?;
+        let handle = state.next_handle();
+        state.tensor_sets.insert(handle, HashMap::new());
+        Ok(TensorSetHandle(handle))
+    }
+
+    fn destroy_tensor_set(&self, tensor_set: TensorSetHandle) {
+        self.state().tensor_sets.remove(&tensor_set.0);
+    }
+
+    fn add_tensor_to_tensor_set(
+        &self,
+        tensor_set: TensorSetHandle,
+        name: &str,
+        tensor: TensorHandle,
+    ) -> Result<(), NrtError> {
+        let mut state = self.initialized_state("nrt_add_tensor_to_tensor_set")?;
+        if !state.tensors.contains_key(&tensor.0) {
+            return Err(NrtError::status(
+                "nrt_add_tensor_to_tensor_set",
+                NrtStatus::InvalidHandle,
+            ));
+        }
+        state
+            .tensor_sets
+            .get_mut(&tensor_set.0)
+            .ok_or_else(|| {
+                NrtError::status("nrt_add_tensor_to_tensor_set", NrtStatus::InvalidHandle)
+            })?
+            .insert(name.to_string(), tensor.0);
+        Ok(())
+    }
+
+    fn get_tensor_from_tensor_set(
+        &self,
+        tensor_set: TensorSetHandle,
+        name: &str,
+    ) -> Result<TensorHandle, NrtError> {
+        let state = self.initialized_state("nrt_get_tensor_from_tensor_set")?;
+        state
+            .tensor_sets
+            .get(&tensor_set.0)
+            .ok_or_else(|| {
+                NrtError::status("nrt_get_tensor_from_tensor_set", NrtStatus::InvalidHandle)
+            })?
+            .get(name)
+            .map(|&tensor| TensorHandle(tensor))
+            .ok_or_else(|| NrtError::status("nrt_get_tensor_from_tensor_set", NrtStatus::Invalid))
+    }
+
+    fn execute(
+        &self,
+        model: ModelHandle,
+        inputs: TensorSetHandle,
+        outputs: TensorSetHandle,
+    ) -> Result<(), NrtError> {
+        let state = self.initialized_state("nrt_execute")?;
+        let tensor_info = &state
+            .models
+            .get(&model.0)
+            .ok_or_else(|| NrtError::status("nrt_execute", NrtStatus::InvalidHandle))?
+            .tensor_info;
+
+        // Resolve every tensor of the model in the matching tensor set.
+        let mut input_handles = Vec::new();
+        let mut output_handles = Vec::new();
+        for info in tensor_info {
+            let (tensor_set, handles) =
+                if info.usage == nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT {
+                    (inputs, &mut input_handles)
+                } else {
+                    (outputs, &mut output_handles)
+                };
+            let tensor = state
+                .tensor_sets
+                .get(&tensor_set.0)
+                .ok_or_else(|| NrtError::status("nrt_execute", NrtStatus::InvalidHandle))?
+                .get(&info.name)
+                .copied()
+                .ok_or_else(|| NrtError::status("nrt_execute", NrtStatus::ExecBadInput))?;
+            let data = state
+                .tensors
+                .get(&tensor)
+                .ok_or_else(|| NrtError::status("nrt_execute", NrtStatus::InvalidHandle))?;
+            if data.len() != info.size {
+                return Err(NrtError::status("nrt_execute", NrtStatus::ExecBadInput));
+            }
+            handles.push(tensor);
+        }
+
+        let input_data: Vec<Vec<u8>> = input_handles
+            .iter()
+            .map(|tensor| state.tensors[tensor].clone())
+            .collect();
+        let mut output_data: Vec<Vec<u8>> = output_handles
+            .iter()
+            .map(|tensor| state.tensors[tensor].clone())
+            .collect();
+        if state.crashing_inputs.contains(&input_data.concat()) {
+            segfault();
+        }
+
+        // The kernel runs without the lock, so models on different cores can execute
+        // at the same time.
+        drop(state);
+        (self.kernel)(&input_data, &mut output_data);
+
+        let mut state = self.state();
+        for (tensor, data) in output_handles.iter().zip(output_data) {
+            match state.tensors.get_mut(tensor) {
+                Some(dst) if data.len() == dst.len() => *dst = data,
+                Some(_) => {}
+                None => return Err(NrtError::status("nrt_execute", NrtStatus::InvalidHandle)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local
+    use super::FakeRuntime;
+    use crate::bindings::nrt;
+    use crate::error::NrtStatus;
+    use crate::runtime::{NeuronRuntime, TensorInfo};
+
+    fn tensor(name: &str, usage: nrt::nrt_tensor_usage_t, size: usize) -> TensorInfo {
+        TensorInfo {
+            name: name.to_string(),
+            usage,
+            size,
+            dtype: nrt::nrt_dtype_NRT_DTYPE_UINT8,
+            shape: vec![size as u32],
+        }
+    }
+
+    #[test]
+    fn fake_runtime_echoes_inputs_into_outputs() {
+        let runtime = FakeRuntime::new(vec![
+            tensor("input0", nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT, 2),
+            tensor("output0", nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT, 4),
+        ]);
+        assert_eq!(
+            runtime.load(b"neff", 0, 1).unwrap_err().nrt_status(),
+            Some(NrtStatus::Uninitialized)
+        );
+        runtime.init().unwrap();
+
+        let model = runtime.load(b"neff", 0, 1).unwrap();
+        let inputs = runtime.allocate_tensor_set().unwrap();
+        let outputs = runtime.allocate_tensor_set().unwrap();
+        let input = runtime
+            .tensor_allocate(
+                nrt::nrt_tensor_placement_t_NRT_TENSOR_PLACEMENT_DEVICE,
+                0,
+                2,
+                "input0",
+            )
+            .unwrap();
+        let output = runtime
+            .tensor_allocate(
+                nrt::nrt_tensor_placement_t_NRT_TENSOR_PLACEMENT_DEVICE,
+                0,
+                4,
+                "output0",
+            )
+            .unwrap();
+        runtime
+            .add_tensor_to_tensor_set(inputs, "input0", input)
+            .unwrap();
+        runtime
+            .add_tensor_to_tensor_set(outputs, "output0", output)
+            .unwrap();
+        runtime.tensor_write(input, &[7, 9], 0).unwrap();
+
+        runtime.execute(model, inputs, outputs).unwrap();
+        let mut data = [0; 4];
+        runtime.tensor_read(output, &mut data, 0).unwrap();
+        assert_eq!(data, [7, 9, 7, 9]);
+        assert_eq!(
+            runtime
+                .tensor_read(output, &mut data, usize::MAX)
+                .unwrap_err()
+                .nrt_status(),
+            Some(NrtStatus::Invalid)
+        );
+        assert_eq!(
+            runtime
+                .tensor_write(input, &[1], usize::MAX)
+                .unwrap_err()
+                .nrt_status(),
+            Some(NrtStatus::Invalid)
+        );
+
+        runtime.tensor_free(input);
+        runtime.tensor_free(output);
+        runtime.unload(model).unwrap();
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+        assert_eq!(runtime.loaded_model_count(), 0);
+    }
+
+    #[test]
+    fn fake_runtime_rejects_placements_past_the_last_core() {
+        let runtime = FakeRuntime::new(Vec::new()).with_nc_count(2);
+        runtime.init().unwrap();
+        assert!(runtime.load(b"neff", 1, 1).is_ok());
+        for (start_nc, nc_count) in [(1, 2), (i32::MAX, 1)] {
+            assert_eq!(
+                runtime
+                    .load(b"neff", start_nc, nc_count)
+                    .unwrap_err()
+                    .nrt_status(),
+                Some(NrtStatus::LoadNotEnoughNc)
+            );
+        }
+    }
+}
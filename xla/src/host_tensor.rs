@@ -0,0 +1,279 @@
+//! Tensors in host memory, with their shape.
+
+// Local
+use crate::bindings::nrt;
+use crate::dtype::{dtype_size, TensorData};
+use crate::error::NrtError;
+use crate::runtime::TensorInfo;
+
+/// The values of a tensor together with its shape, used for both model inputs and
+/// outputs.
+///
+/// The number of elements always matches the shape. Dimensions are in row-major order,
+/// and a scalar has an empty shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostTensor {
+    shape: Vec<usize>,
+    data: TensorData,
+}
+
+impl HostTensor {
+    /// Creates a tensor from its shape and values.
+    ///
+    /// Returns [`NrtError::InvalidShape`] if the shape does not have as many elements as
+    /// `data`.
+    pub fn new<D: Into<TensorData>>(shape: Vec<usize>, data: D) -> Result<Self, NrtError> {
+        let data = data.into();
+        if element_count(&shape) != Some(data.len()) {
+            return Err(NrtError::InvalidShape {
+                shape,
+                len: data.len(),
+            });
+        }
+        Ok(HostTensor { shape, data })
+    }
+
+    /// Creates a one dimensional tensor.
+    pub fn from_vec<D: Into<TensorData>>(data: D) -> Self {
+        let data = data.into();
+        HostTensor {
+            shape: vec![data.len()],
+            data,
+        }
+    }
+
+    /// A zero-filled tensor with the dtype and shape of the tensor described by
+    /// `tensor_info`.
+    ///
+    /// Returns [`NrtError::InvalidShape`] if the reported shape does not match the
+    /// tensor's size.
+    pub fn zeros(tensor_info: &TensorInfo) -> Result<Self, NrtError> {
+        HostTensor::new(reported_shape(tensor_info), zero_data(tensor_info)?)
+    }
+
+    /// A zero-filled tensor to read the output described by `tensor_info` into.
+    ///
+    /// Unlike [`HostTensor::zeros`], a reported shape that does not match the tensor's
+    /// size gives a one dimensional tensor, so the output's bytes can still be read.
+    pub fn output_zeros(tensor_info: &TensorInfo) -> Result<Self, NrtError> {
+        let data = zero_data(tensor_info)?;
+        Ok(HostTensor {
+            shape: output_shape(tensor_info)?,
+            data,
+        })
+    }
+
+    /// The `nrt_dtype_t` of the values.
+    pub fn dtype(&self) -> nrt::nrt_dtype_t {
+        self.data.dtype()
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    pub fn data(&self) -> &TensorData {
+        &self.data
+    }
+
+    pub fn into_data(self) -> TensorData {
+        self.data
+    }
+
+    /// The number of elements.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The values as the bytes stored in a tensor.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+
+    /// The values as a mutable byte buffer to read a tensor into.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self.data.as_bytes_mut()
+    }
+
+    /// Changes the shape without moving any values. The new shape must have the same
+    /// number of elements.
+    pub fn reshape(self, shape: Vec<usize>) -> Result<Self, NrtError> {
+        HostTensor::new(shape, self.data)
+    }
+
+    /// Reshapes the tensor to one dimension.
+    pub fn flatten(self) -> Self {
+        HostTensor::from_vec(self.data)
+    }
+
+    /// Checks that the tensor has the dtype, shape and size of the tensor described by
+    /// `tensor_info`.
+    pub fn check_matches(&self, tensor_info: &TensorInfo) -> Result<(), NrtError> {
+        self.check(tensor_info, reported_shape(tensor_info))
+    }
+
+    /// Checks that the tensor can hold the output described by `tensor_info`, with the
+    /// shape given by [`HostTensor::output_zeros`].
+    pub fn check_output_matches(&self, tensor_info: &TensorInfo) -> Result<(), NrtError> {
+        self.check(tensor_info, output_shape(tensor_info)?)
+    }
+
+    fn check(&self, tensor_info: &TensorInfo, expected: Vec<usize>) -> Result<(), NrtError> {
+        if self.dtype() != tensor_info.dtype {
+            return Err(NrtError::DtypeMismatch {
+                tensor: tensor_info.name.clone(),
+                expected: tensor_info.dtype,
+                actual: self.dtype(),
+            });
+        }
+        if self.shape != expected {
+            return Err(NrtError::ShapeMismatch {
+                tensor: tensor_info.name.clone(),
+                expected,
+                actual: self.shape.clone(),
+            });
+        }
+        if self.as_bytes().len() != tensor_info.size {
+            return Err(NrtError::SizeMismatch {
+                tensor: tensor_info.name.clone(),
+                expected: tensor_info.size,
+                actual: self.as_bytes().len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The shape of a model's tensor as NRT reports it.
+fn reported_shape(tensor_info: &TensorInfo) -> Vec<usize> {
+    tensor_info.shape.iter().map(|&dim| dim as usize).collect()
+}
+
+/// The number of elements of a model's tensor, from its size in bytes.
+fn len_of(tensor_info: &TensorInfo) -> usize {
+    tensor_info.size / dtype_size(tensor_info.dtype).unwrap_or(1)
+}
+
+/// The number of elements of `shape`, or `None` if it does not fit in a `usize`.
+fn element_count(shape: &[usize]) -> Option<usize> {
+    shape
+        .iter()
+        .try_fold(1usize, |count, &dim| count.checked_mul(dim))
+}
+
+/// Zeros for every element of a model's tensor.
+fn zero_data(tensor_info: &TensorInfo) -> Result<TensorData, NrtError> {
+    TensorData::zeros(tensor_info.dtype, len_of(tensor_info)).ok_or_else(|| {
+        NrtError::UnsupportedDtype {
+            tensor: tensor_info.name.clone(),
+            dtype: tensor_info.dtype,
+        }
+    })
+}
+
+/// The shape an output is read with. A reported shape that does not match the tensor's
+/// size is replaced by a flat one, so the output can still be used as raw values.
+fn output_shape(tensor_info: &TensorInfo) -> Result<Vec<usize>, NrtError> {
+    let shape = reported_shape(tensor_info);
+    let len = len_of(tensor_info);
+    match element_count(&shape) {
+        Some(count) if count == len => Ok(shape),
+        Some(_) => Ok(vec![len]),
+        None => Err(NrtError::InvalidShape { shape, len }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local
+    use super::HostTensor;
+    use crate::bindings::nrt;
+    use crate::dtype::TensorData;
+    use crate::error::NrtError;
+    use crate::runtime::TensorInfo;
+    use crate::test_util::f32_tensor;
+
+    #[test]
+    fn host_tensor_validates_and_reshapes() {
+        let tensor = HostTensor::new(vec![2, 3], vec![0i32, 1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(tensor.dtype(), nrt::nrt_dtype_NRT_DTYPE_INT32);
+        assert!(matches!(
+            HostTensor::new(vec![2, 2], vec![0i32; 6]),
+            Err(NrtError::InvalidShape { len: 6, .. })
+        ));
+
+        let tensor = tensor.reshape(vec![3, 2]).unwrap();
+        assert_eq!(tensor.shape(), [3, 2]);
+        assert!(tensor.clone().reshape(vec![4]).is_err());
+        assert_eq!(tensor.clone().flatten().shape(), [6]);
+        assert_eq!(
+            tensor.into_data(),
+            TensorData::Int32(vec![0, 1, 2, 3, 4, 5])
+        );
+
+        let info = f32_tensor(
+            "hidden",
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            vec![6, 8],
+        );
+        let zeros = HostTensor::zeros(&info).unwrap();
+        assert_eq!(zeros.shape(), [6, 8]);
+        assert!(zeros.check_matches(&info).is_ok());
+        assert!(matches!(
+            HostTensor::from_vec(vec![0f32; 48]).check_matches(&info),
+            Err(NrtError::ShapeMismatch { .. })
+        ));
+        assert!(matches!(
+            HostTensor::new(vec![6, 8], vec![0i32; 48])
+                .unwrap()
+                .check_matches(&info),
+            Err(NrtError::DtypeMismatch { .. })
+        ));
+
+        assert!(matches!(
+            HostTensor::new(vec![usize::MAX, 2], vec![0i32; 2]),
+            Err(NrtError::InvalidShape { len: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn only_outputs_are_flattened_when_their_shape_does_not_match_their_size() {
+        // A size of 6 * 8 values.
+        let info = TensorInfo {
+            shape: vec![6, 4],
+            ..f32_tensor(
+                "hidden",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                vec![6, 8],
+            )
+        };
+        // Outputs are read flat, like the raw bytes they are.
+        let output = HostTensor::output_zeros(&info).unwrap();
+        assert_eq!(output.shape(), [48]);
+        assert!(output.check_output_matches(&info).is_ok());
+
+        // Inputs must match the reported shape, which no value does.
+        assert!(matches!(
+            HostTensor::zeros(&info),
+            Err(NrtError::InvalidShape { len: 48, .. })
+        ));
+        assert!(output.check_matches(&info).is_err());
+        assert!(HostTensor::new(vec![6, 4], vec![0f32; 24])
+            .unwrap()
+            .check_matches(&info)
+            .is_err());
+
+        let info = TensorInfo {
+            shape: vec![u32::MAX; 4],
+            ..info
+        };
+        assert!(matches!(
+            HostTensor::output_zeros(&info),
+            Err(NrtError::InvalidShape { len: 48, .. })
+        ));
+    }
+}
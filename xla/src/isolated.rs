@@ -0,0 +1,1022 @@
+//! Runs models in a worker process so that a crash in libnrt does not kill the caller.
+//!
+//! `nrt_load` and `nrt_execute` can die with SIGSEGV, which no `Result` can catch.
+//! [`IsolatedRunner`] instead sends every load and run to a child process over its
+//! stdin, reads the responses from a pipe of their own, and reports the death of the
+//! child as [`NrtError::RuntimeCrashed`]. A worker that does not respond in time is
+//! killed and reported the same way. A new worker is started on the next call, and
+//! models that were loaded in the old one are loaded again before they run.
+//!
+//! The worker is the calling program itself, started again with `XLA_NRT_WORKER` set
+//! in its environment to the file descriptor of the response pipe. Programs that use
+//! an `IsolatedRunner` must therefore call [`serve_if_worker`] at the start of `main`,
+//! before they do anything else:
+//!
+//! ```no_run
+//! # use xla::isolated::{serve_if_worker, IsolatedRunner};
+//! # use xla::shared::nrt_runtime;
+//! serve_if_worker(|| nrt_runtime(None));
+//! let runner = IsolatedRunner::new()?;
+//! let results = runner.run_trn("model.neff", "model", &[], vec![], vec![])?;
+//! # Ok::<(), xla::error::NrtError>(())
+//! ```
+
+// System
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, PipeReader, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+// Third party
+use indexmap::IndexMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// Local
+use crate::bindings::nrt;
+use crate::dtype::{dtype_size, TensorData};
+use crate::error::{IoOperation, NrtError, NrtStatus};
+use crate::executor::spawn_thread;
+use crate::host_tensor::HostTensor;
+use crate::model::LoadOptions;
+use crate::runtime::TensorInfo;
+use crate::shared::SharedRuntime;
+use crate::xla_runner::{run_once, LoadedModel, RunOnce, RunTimings, XLARunResults, XLARunner};
+
+/// The environment variable that makes a process serve as a worker, set to the file
+/// descriptor the worker writes its responses to.
+const WORKER_ENV: &str = "XLA_NRT_WORKER";
+/// How much of the end of the worker's stderr is kept for crash reports.
+const STDERR_TAIL: usize = 64 * 1024;
+/// How long a worker whose pipes closed is given to exit before it is killed.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a worker is given to start and to respond to each request by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// The largest message accepted, in bytes of JSON. Anything longer means the stream is
+/// out of sync.
+const MAX_MESSAGE_LEN: u64 = 16 << 20;
+/// The most blobs accepted with one message.
+const MAX_BLOBS: u64 = 1 << 16;
+/// How many bytes of outputs a response may hold beyond the size of the model's
+/// outputs.
+const OUTPUT_SLACK: u64 = 1 << 20;
+
+#[derive(Serialize, Deserialize)]
+enum Request {
+    Load {
+        neff_path: PathBuf,
+        name: String,
+        options: LoadOptions,
+    },
+    /// Followed by the bytes of each input.
+    Run {
+        model: u64,
+        inputs: Vec<TensorHeader>,
+    },
+    Unload {
+        model: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    Ready,
+    Loaded {
+        model: u64,
+        tensor_info: Vec<TensorInfo>,
+        load_timings: RunTimings,
+    },
+    /// Followed by the bytes of each output.
+    Ran {
+        outputs: Vec<TensorHeader>,
+        timings: RunTimings,
+    },
+    Unloaded,
+    Failed(NrtError),
+}
+
+/// A [`HostTensor`] without its values, which are sent separately as raw bytes.
+#[derive(Serialize, Deserialize)]
+struct TensorHeader {
+    name: String,
+    dtype: nrt::nrt_dtype_t,
+    shape: Vec<usize>,
+}
+
+impl TensorHeader {
+    fn new(name: &str, tensor: &HostTensor) -> Self {
+        TensorHeader {
+            name: name.to_string(),
+            dtype: tensor.dtype(),
+            shape: tensor.shape().to_vec(),
+        }
+    }
+
+    fn into_tensor(self, bytes: &[u8]) -> Result<(String, HostTensor), NrtError> {
+        let element_size = dtype_size(self.dtype).unwrap_or(1);
+        let mut data =
+            TensorData::zeros(self.dtype, bytes.len() / element_size).ok_or_else(|| {
+                NrtError::UnsupportedDtype {
+                    tensor: self.name.clone(),
+                    dtype: self.dtype,
+                }
+            })?;
+        if data.as_bytes().len() != bytes.len() {
+            return Err(NrtError::SizeMismatch {
+                tensor: self.name,
+                expected: data.as_bytes().len(),
+                actual: bytes.len(),
+            });
+        }
+        data.as_bytes_mut().copy_from_slice(bytes);
+        Ok((self.name, HostTensor::new(self.shape, data)?))
+    }
+}
+
+/// Writes a message as its JSON length and JSON followed by the length and contents of
+/// each blob.
+fn write_frame<W: Write, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+    blobs: &[&[u8]],
+) -> std::io::Result<()> {
+    let json = serde_json::to_vec(message)?;
+    writer.write_all(&(json.len() as u64).to_le_bytes())?;
+    writer.write_all(&json)?;
+    writer.write_all(&(blobs.len() as u64).to_le_bytes())?;
+    for blob in blobs {
+        writer.write_all(&(blob.len() as u64).to_le_bytes())?;
+        writer.write_all(blob)?;
+    }
+    writer.flush()
+}
+
+/// Reads a frame written by [`write_frame`] whose blobs hold at most `max_blob_bytes`
+/// bytes in total. A frame that is larger fails with `InvalidData` before anything is
+/// allocated for it.
+fn read_frame<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+    max_blob_bytes: u64,
+) -> std::io::Result<(T, Vec<Vec<u8>>)> {
+    let read_blob = |reader: &mut R, max: u64| -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; read_len(reader, max)?];
+        reader.read_exact(&mut buf)?;
+        Ok(buf)
+    };
+    let message = serde_json::from_slice(&read_blob(reader, MAX_MESSAGE_LEN)?)?;
+    let mut remaining = max_blob_bytes;
+    let blobs = (0..read_len(reader, MAX_BLOBS)?)
+        .map(|_| {
+            let blob = read_blob(reader, remaining)?;
+            remaining -= blob.len() as u64;
+            Ok(blob)
+        })
+        .collect::<std::io::Result<_>>()?;
+    Ok((message, blobs))
+}
+
+/// Reads a length, which must not be more than `max`.
+fn read_len<R: Read>(reader: &mut R, max: u64) -> std::io::Result<usize> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    if len > max {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("frame length {} is larger than {}", len, max),
+        ));
+    }
+    Ok(len as usize)
+}
+
+/// A running worker process.
+struct Worker {
+    child: Child,
+    /// Closing stdin tells the worker to unload everything and exit.
+    stdin: Option<ChildStdin>,
+    /// The pipe the worker writes its responses to.
+    responses: BufReader<PipeReader>,
+    /// Passes the worker's stderr through and returns its tail once the worker exits.
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    watchdog: Watchdog,
+}
+
+impl Worker {
+    /// Starts a worker and waits up to `timeout` for it to be ready.
+    fn spawn(program: &Path, args: &[OsString], timeout: Duration) -> Result<Self, NrtError> {
+        if std::env::var_os(WORKER_ENV).is_some() {
+            return Err(NrtError::NestedWorker);
+        }
+        let spawn_error = |e| NrtError::Io {
+            operation: IoOperation::Spawn,
+            path: program.to_path_buf(),
+            source: Arc::new(e),
+        };
+        let (responses, response_writer) = std::io::pipe().map_err(spawn_error)?;
+        let response_fd = response_writer.as_raw_fd();
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env(WORKER_ENV, response_fd.to_string())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped());
+        // SAFETY: `fcntl` is async-signal-safe, and only clears the close-on-exec flag of
+        // the write end of the response pipe, so that it stays open in the worker.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(response_fd, libc::F_SETFD, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().map_err(spawn_error)?;
+        // Only the worker may hold the write end, so that its death ends the pipe.
+        drop(response_writer);
+
+        let child_stderr = child.stderr.take().expect("stderr is piped");
+        let mut worker = Worker {
+            stdin: child.stdin.take(),
+            responses: BufReader::new(responses),
+            child,
+            stderr: None,
+            watchdog: Watchdog::default(),
+        };
+        worker.stderr = Some(spawn_thread(
+            format!("xla-worker-stderr-{}", worker.child.id()),
+            move || pass_through(child_stderr),
+        )?);
+        worker.watchdog.watch(worker.child.id())?;
+
+        worker.watchdog.set_deadline(Some(Instant::now() + timeout));
+        let ready = read_frame(&mut worker.responses, 0);
+        worker.watchdog.set_deadline(None);
+        match ready {
+            Ok((Response::Ready, _)) => Ok(worker),
+            Ok((Response::Failed(e), _)) => Err(e),
+            Ok(_) | Err(_) => Err(worker.crashed()),
+        }
+    }
+
+    /// Sends `request` and reads the response, killing the worker if it takes longer
+    /// than `timeout`.
+    fn exchange(
+        &mut self,
+        request: &Request,
+        blobs: &[&[u8]],
+        max_response_bytes: u64,
+        timeout: Duration,
+    ) -> std::io::Result<(Response, Vec<Vec<u8>>)> {
+        let stdin = self.stdin.as_mut().ok_or(ErrorKind::BrokenPipe)?;
+        self.watchdog.set_deadline(Some(Instant::now() + timeout));
+        let response = write_frame(stdin, request, blobs)
+            .and_then(|()| read_frame(&mut self.responses, max_response_bytes));
+        self.watchdog.set_deadline(None);
+        response
+    }
+
+    /// Stops a worker that stopped responding and describes how it died.
+    fn crashed(mut self) -> NrtError {
+        let status = self.stop();
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|stderr| stderr.join().ok())
+            .unwrap_or_default();
+        NrtError::RuntimeCrashed {
+            signal: status.and_then(|status| status.signal()),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        }
+    }
+
+    /// Closes the worker's stdin and waits for it to exit, killing it if it does not.
+    fn stop(&mut self) -> Option<ExitStatus> {
+        // The watchdog kills by process id, so it must be done before the worker is
+        // reaped and its id can be reused.
+        self.watchdog.stop();
+        self.stdin = None;
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        loop {
+            match self.child.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                _ => {
+                    let _ = self.child.kill();
+                    return self.child.wait().ok();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Copies the worker's stderr to ours and returns its last [`STDERR_TAIL`] bytes.
+fn pass_through(mut child_stderr: impl Read) -> Vec<u8> {
+    let mut tail = Vec::new();
+    let mut buf = [0; 8192];
+    while let Ok(n @ 1..) = child_stderr.read(&mut buf) {
+        let _ = std::io::stderr().write_all(&buf[..n]);
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > STDERR_TAIL {
+            tail.drain(..tail.len() - STDERR_TAIL);
+        }
+    }
+    tail
+}
+
+/// Kills a worker that is still working on a request when its deadline passes.
+#[derive(Default)]
+struct Watchdog {
+    state: Arc<(Mutex<WatchdogState>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    deadline: Option<Instant>,
+    stopped: bool,
+}
+
+impl Watchdog {
+    /// Starts watching the process `pid`.
+    fn watch(&mut self, pid: u32) -> Result<(), NrtError> {
+        let state = self.state.clone();
+        let thread = spawn_thread(format!("xla-worker-watchdog-{}", pid), move || {
+            let (state, deadline_changed) = &*state;
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            while !state.stopped {
+                state = match state.deadline {
+                    Some(deadline) if deadline <= Instant::now() => {
+                        // SAFETY: `kill` has no memory effects. The worker is not reaped
+                        // before the watchdog is stopped, so `pid` is still the worker.
+                        unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+                        state.deadline = None;
+                        state
+                    }
+                    Some(deadline) => {
+                        deadline_changed
+                            .wait_timeout(state, deadline - Instant::now())
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .0
+                    }
+                    None => deadline_changed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner),
+                };
+            }
+        })?;
+        self.thread = Some(thread);
+        Ok(())
+    }
+
+    fn set_deadline(&self, deadline: Option<Instant>) {
+        let (state, deadline_changed) = &*self.state;
+        state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .deadline = deadline;
+        deadline_changed.notify_one();
+    }
+
+    /// Stops watching, and returns once the worker can no longer be killed.
+    fn stop(&mut self) {
+        let (state, deadline_changed) = &*self.state;
+        state.lock().unwrap_or_else(PoisonError::into_inner).stopped = true;
+        deadline_changed.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Spawns the worker processes and restarts them after a crash.
+struct Supervisor {
+    program: PathBuf,
+    args: Vec<OsString>,
+    /// How long a worker is given to start and to respond to each request.
+    timeout: Duration,
+    worker: Option<Worker>,
+    /// Incremented every time a worker is started.
+    generation: u64,
+}
+
+impl Supervisor {
+    /// Sends `request` to the worker, starting one if there is none. A response with
+    /// more than `max_response_bytes` bytes of blobs is treated as a crash.
+    fn call(
+        &mut self,
+        request: &Request,
+        blobs: &[&[u8]],
+        max_response_bytes: u64,
+    ) -> Result<(Response, Vec<Vec<u8>>), NrtError> {
+        let worker = match &mut self.worker {
+            Some(worker) => worker,
+            worker => {
+                self.generation += 1;
+                worker.insert(Worker::spawn(&self.program, &self.args, self.timeout)?)
+            }
+        };
+        match worker.exchange(request, blobs, max_response_bytes, self.timeout) {
+            Ok((Response::Failed(e), _)) => Err(e),
+            Ok(response) => Ok(response),
+            Err(_) => Err(self.worker.take().expect("worker is running").crashed()),
+        }
+    }
+
+    /// Stops a worker that sent a response that does not match the request.
+    fn unexpected_response(&mut self) -> NrtError {
+        match self.worker.take() {
+            Some(worker) => worker.crashed(),
+            None => NrtError::RuntimeCrashed {
+                signal: None,
+                stderr: String::new(),
+            },
+        }
+    }
+
+    /// Whether the worker started in `generation` is still running.
+    fn is_running(&self, generation: u64) -> bool {
+        self.worker.is_some() && self.generation == generation
+    }
+}
+
+/// Runs models in a worker process that is restarted if it crashes.
+///
+/// This offers the same loads and runs as [`XLARunner`], but inputs and outputs are
+/// copied through a pipe to and from the worker. Calls are serialized, since a runner
+/// has a single worker.
+pub struct IsolatedRunner {
+    supervisor: Arc<Mutex<Supervisor>>,
+}
+
+impl IsolatedRunner {
+    /// Creates a runner whose worker is the current executable, with no arguments. The
+    /// worker is started by the first load.
+    pub fn new() -> Result<Self, NrtError> {
+        let program = std::env::current_exe().map_err(|e| NrtError::Io {
+            operation: IoOperation::Read,
+            path: PathBuf::from("/proc/self/exe"),
+            source: Arc::new(e),
+        })?;
+        Ok(Self::with_worker(program, Vec::new()))
+    }
+
+    /// Creates a runner whose worker is `program` started with `args`. The program
+    /// must call [`serve_if_worker`].
+    pub fn with_worker<P: Into<PathBuf>>(program: P, args: Vec<OsString>) -> Self {
+        IsolatedRunner {
+            supervisor: Arc::new(Mutex::new(Supervisor {
+                program: program.into(),
+                args,
+                timeout: DEFAULT_TIMEOUT,
+                worker: None,
+                generation: 0,
+            })),
+        }
+    }
+
+    /// Sets how long the worker is given to start, and to respond to each load, run and
+    /// unload. A worker that takes longer is killed, and the call fails with
+    /// [`NrtError::RuntimeCrashed`]. This is 10 minutes by default.
+    pub fn set_timeout(&self, timeout: Duration) {
+        lock(&self.supervisor).timeout = timeout;
+    }
+
+    /// Loads a NEFF in the worker, like [`XLARunner::load`].
+    pub fn load<P: AsRef<Path>>(&self, neff_path: P) -> Result<IsolatedModel, NrtError> {
+        self.load_with_options(neff_path, LoadOptions::default())
+    }
+
+    /// Loads a NEFF in the worker, like [`XLARunner::load_with_options`].
+    pub fn load_with_options<P: AsRef<Path>>(
+        &self,
+        neff_path: P,
+        options: LoadOptions,
+    ) -> Result<IsolatedModel, NrtError> {
+        let neff_path = neff_path.as_ref();
+        self.load_as(neff_path, &neff_path.to_string_lossy(), options)
+    }
+
+    /// Loads, runs and unloads a NEFF in the worker, like [`XLARunner::run_trn`].
+    pub fn run_trn(
+        &self,
+        neff_path: &str,
+        run_name: &str,
+        input_names: &[&str],
+        inputs: Vec<TensorData>,
+        input_shapes: Vec<Vec<u64>>,
+    ) -> Result<XLARunResults, NrtError> {
+        run_once(
+            || self.load_as(Path::new(neff_path), run_name, LoadOptions::default()),
+            input_names,
+            inputs,
+            input_shapes,
+        )
+    }
+
+    fn load_as(
+        &self,
+        neff_path: &Path,
+        name: &str,
+        options: LoadOptions,
+    ) -> Result<IsolatedModel, NrtError> {
+        let mut model = IsolatedModel {
+            supervisor: self.supervisor.clone(),
+            neff_path: neff_path.to_path_buf(),
+            name: name.to_string(),
+            options,
+            tensor_info: Vec::new(),
+            load_timings: RunTimings::default(),
+            loaded: None,
+        };
+        model.ensure_loaded(&mut lock(&self.supervisor))?;
+        Ok(model)
+    }
+}
+
+/// A model loaded in the worker of an [`IsolatedRunner`].
+///
+/// If the worker crashes, the model is loaded again in the next worker the next time it
+/// is run.
+pub struct IsolatedModel {
+    supervisor: Arc<Mutex<Supervisor>>,
+    neff_path: PathBuf,
+    name: String,
+    options: LoadOptions,
+    tensor_info: Vec<TensorInfo>,
+    load_timings: RunTimings,
+    /// The generation of the worker the model is loaded in and its id there.
+    loaded: Option<(u64, u64)>,
+}
+
+impl IsolatedModel {
+    /// The name errors from this model are reported with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The model's input and output tensors, as reported by the worker.
+    pub fn tensor_info(&self) -> &[TensorInfo] {
+        &self.tensor_info
+    }
+
+    /// How long each step of loading the model in the worker took, like
+    /// [`LoadedModel::load_timings`]. After a crash these are the timings of the reload.
+    pub fn load_timings(&self) -> RunTimings {
+        self.load_timings
+    }
+
+    /// Runs the model in the worker, like [`LoadedModel::run`].
+    pub fn run(&mut self, inputs: HashMap<String, HostTensor>) -> Result<XLARunResults, NrtError> {
+        let supervisor = self.supervisor.clone();
+        let mut supervisor = lock(&supervisor);
+        let model = self.ensure_loaded(&mut supervisor)?;
+
+        let (headers, blobs): (Vec<_>, Vec<_>) = inputs
+            .iter()
+            .map(|(name, tensor)| (TensorHeader::new(name, tensor), tensor.as_bytes()))
+            .unzip();
+        let request = Request::Run {
+            model,
+            inputs: headers,
+        };
+        let output_bytes = self
+            .tensor_info
+            .iter()
+            .filter(|info| info.usage == nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT)
+            .fold(OUTPUT_SLACK, |total, info| {
+                total.saturating_add(info.size as u64)
+            });
+        match supervisor.call(&request, &blobs, output_bytes)? {
+            (Response::Ran { outputs, timings }, blobs) if blobs.len() == outputs.len() => {
+                let output = outputs
+                    .into_iter()
+                    .zip(&blobs)
+                    .map(|(header, bytes)| header.into_tensor(bytes))
+                    .collect::<Result<IndexMap<_, _>, _>>()?;
+                Ok(XLARunResults {
+                    output,
+                    debug_ir: None,
+                    runtime: timings.execute,
+                    timings,
+                })
+            }
+            _ => Err(supervisor.unexpected_response()),
+        }
+    }
+
+    /// Unloads the model from the worker now, returning any error that dropping the
+    /// model would ignore.
+    pub fn unload(mut self) -> Result<(), NrtError> {
+        self.unload_inner()
+    }
+
+    /// Loads the model if the worker it was loaded in is gone, and returns its id.
+    fn ensure_loaded(&mut self, supervisor: &mut Supervisor) -> Result<u64, NrtError> {
+        if let Some((generation, model)) = self.loaded {
+            if supervisor.is_running(generation) {
+                return Ok(model);
+            }
+        }
+        self.loaded = None;
+        let request = Request::Load {
+            neff_path: self.neff_path.clone(),
+            name: self.name.clone(),
+            options: self.options,
+        };
+        match supervisor.call(&request, &[], 0)? {
+            (
+                Response::Loaded {
+                    model,
+                    tensor_info,
+                    load_timings,
+                },
+                _,
+            ) => {
+                self.tensor_info = tensor_info;
+                self.load_timings = load_timings;
+                self.loaded = Some((supervisor.generation, model));
+                Ok(model)
+            }
+            _ => Err(supervisor.unexpected_response()),
+        }
+    }
+
+    fn unload_inner(&mut self) -> Result<(), NrtError> {
+        let Some((generation, model)) = self.loaded.take() else {
+            return Ok(());
+        };
+        let mut supervisor = lock(&self.supervisor);
+        if !supervisor.is_running(generation) {
+            return Ok(());
+        }
+        match supervisor.call(&Request::Unload { model }, &[], 0)? {
+            (Response::Unloaded, _) => Ok(()),
+            _ => Err(supervisor.unexpected_response()),
+        }
+    }
+}
+
+impl RunOnce for IsolatedModel {
+    fn load_timings(&self) -> RunTimings {
+        IsolatedModel::load_timings(self)
+    }
+
+    fn run(&mut self, inputs: HashMap<String, HostTensor>) -> Result<XLARunResults, NrtError> {
+        IsolatedModel::run(self, inputs)
+    }
+
+    fn unload(self) -> Result<(), NrtError> {
+        IsolatedModel::unload(self)
+    }
+}
+
+impl Drop for IsolatedModel {
+    fn drop(&mut self) {
+        // There is no way to report the error from here; use `IsolatedModel::unload`.
+        let _ = self.unload_inner();
+    }
+}
+
+fn lock(supervisor: &Mutex<Supervisor>) -> MutexGuard<'_, Supervisor> {
+    // A crash is handled before the lock is released, so the state is consistent even
+    // if a thread panicked while holding it.
+    supervisor.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Serves the requests of an [`IsolatedRunner`] and exits if this process was started
+/// as its worker, and returns immediately otherwise.
+///
+/// `runtime` is only called in the worker, to get the runtime that models are loaded
+/// on. The worker exits once the runner is dropped.
+pub fn serve_if_worker<F>(runtime: F)
+where
+    F: FnOnce() -> Result<Arc<SharedRuntime>, NrtError>,
+{
+    let Some(response_fd) = std::env::var_os(WORKER_ENV) else {
+        return;
+    };
+    let code = match serve(&response_fd, runtime) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("xla worker: {}", e);
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+fn serve<F>(response_fd: &std::ffi::OsStr, runtime: F) -> std::io::Result<()>
+where
+    F: FnOnce() -> Result<Arc<SharedRuntime>, NrtError>,
+{
+    let response_fd: RawFd = response_fd
+        .to_str()
+        .and_then(|fd| fd.parse().ok())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a file descriptor", WORKER_ENV),
+            )
+        })?;
+    // SAFETY: `fcntl` has no memory effects. Programs the worker starts must not keep
+    // the response pipe open after the worker dies.
+    if unsafe { libc::fcntl(response_fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: the runner left the write end of its response pipe open as `response_fd`
+    // for this process alone, and `fcntl` checked that it is open.
+    let mut responses = BufWriter::new(unsafe { File::from_raw_fd(response_fd) });
+    let mut stdin = BufReader::new(std::io::stdin().lock());
+
+    let runner = match runtime().and_then(|shared| XLARunner::with_shared_runtime(&shared)) {
+        Ok(runner) => runner,
+        Err(e) => return write_frame(&mut responses, &Response::Failed(e), &[]),
+    };
+    write_frame(&mut responses, &Response::Ready, &[])?;
+
+    let mut models = HashMap::new();
+    let mut next_model = 0;
+    loop {
+        // The runner is trusted to only send inputs the caller gave it.
+        let (request, blobs) = match read_frame(&mut stdin, u64::MAX) {
+            Ok(frame) => frame,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let (response, outputs) =
+            match handle_request(&runner, &mut models, &mut next_model, request, blobs) {
+                Ok(response) => response,
+                Err(e) => (Response::Failed(e), Vec::new()),
+            };
+        let outputs: Vec<&[u8]> = outputs.iter().map(HostTensor::as_bytes).collect();
+        write_frame(&mut responses, &response, &outputs)?;
+    }
+}
+
+fn handle_request(
+    runner: &XLARunner,
+    models: &mut HashMap<u64, LoadedModel>,
+    next_model: &mut u64,
+    request: Request,
+    blobs: Vec<Vec<u8>>,
+) -> Result<(Response, Vec<HostTensor>), NrtError> {
+    let unknown_model = || NrtError::status("xla_worker", NrtStatus::InvalidHandle);
+    match request {
+        Request::Load {
+            neff_path,
+            name,
+            options,
+        } => {
+            let model = runner.load_as(&neff_path, &name, options)?;
+            let tensor_info = model.model().tensor_info().to_vec();
+            let load_timings = model.load_timings();
+            *next_model += 1;
+            models.insert(*next_model, model);
+            let response = Response::Loaded {
+                model: *next_model,
+                tensor_info,
+                load_timings,
+            };
+            Ok((response, Vec::new()))
+        }
+        Request::Run { model, inputs } => {
+            let model = models.get_mut(&model).ok_or_else(unknown_model)?;
+            if blobs.len() != inputs.len() {
+                return Err(NrtError::status("xla_worker", NrtStatus::Invalid));
+            }
+            let inputs = inputs
+                .into_iter()
+                .zip(&blobs)
+                .map(|(header, bytes)| header.into_tensor(bytes))
+                .collect::<Result<_, _>>()?;
+            let results = model.run(inputs)?;
+            let (headers, outputs) = results
+                .output
+                .into_iter()
+                .map(|(name, tensor)| (TensorHeader::new(&name, &tensor), tensor))
+                .unzip();
+            let response = Response::Ran {
+                outputs: headers,
+                timings: results.timings,
+            };
+            Ok((response, outputs))
+        }
+        Request::Unload { model } => {
+            models.remove(&model).ok_or_else(unknown_model)?.unload()?;
+            Ok((Response::Unloaded, Vec::new()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Local
+    use super::{read_frame, serve_if_worker, write_frame, IsolatedRunner, Response};
+    use crate::error::NrtError;
+    use crate::fake::FakeRuntime;
+    use crate::host_tensor::HostTensor;
+    use crate::shared::SharedRuntime;
+    use crate::test_util::{echo_model, NEFF_PATH};
+    const CRASHING_NEFF: &[u8] = b"a NEFF that segfaults";
+    const CRASHING_INPUT: [f32; 2] = [-1.0, -1.0];
+    const HANGING_INPUT: [f32; 2] = [-2.0, -2.0];
+
+    fn as_bytes(values: [f32; 2]) -> Vec<u8> {
+        values.iter().flat_map(|x| x.to_ne_bytes()).collect()
+    }
+
+    /// The worker of the other tests, which start this test binary again to run only
+    /// this test. Does nothing when the tests are run normally.
+    #[test]
+    fn isolated_worker() {
+        serve_if_worker(|| {
+            let fake =
+                FakeRuntime::new(echo_model(vec![2])).with_kernel(Arc::new(|inputs, outputs| {
+                    if inputs[0] == as_bytes(HANGING_INPUT) {
+                        std::thread::sleep(Duration::from_secs(3600));
+                    }
+                    outputs[0].copy_from_slice(&inputs[0]);
+                }));
+            fake.crash_on_load(CRASHING_NEFF.to_vec());
+            fake.crash_on_execute(as_bytes(CRASHING_INPUT));
+            Ok(SharedRuntime::new(Arc::new(fake)))
+        });
+    }
+
+    /// A worker that never becomes ready.
+    #[test]
+    fn hanging_worker() {
+        serve_if_worker(|| loop {
+            std::thread::sleep(Duration::from_secs(3600));
+        });
+    }
+
+    /// A worker that tries to start a worker of its own.
+    #[test]
+    fn nested_worker() {
+        serve_if_worker(|| {
+            IsolatedRunner::new()?.load(NEFF_PATH)?;
+            unreachable!("a worker started a worker")
+        });
+    }
+
+    /// A runner whose worker runs the test `worker` of this test binary.
+    fn runner(worker: &str) -> IsolatedRunner {
+        let test = format!("isolated::tests::{}", worker);
+        let args = ["--exact", &test, "--nocapture"];
+        IsolatedRunner::with_worker(
+            std::env::current_exe().unwrap(),
+            args.iter().map(OsString::from).collect(),
+        )
+    }
+
+    #[test]
+    fn isolated_runner_survives_a_crashing_load() {
+        let runner = runner("isolated_worker");
+        let inputs = |values: Vec<f32>| {
+            HashMap::from([("input0".to_string(), HostTensor::from_vec(values))])
+        };
+
+        let mut model = runner.load(NEFF_PATH).unwrap();
+        assert_eq!(model.tensor_info().len(), 2);
+        let results = model.run(inputs(vec![1.0, 2.0])).unwrap();
+        assert_eq!(
+            results.output["output0"],
+            HostTensor::from_vec(vec![1f32, 2.0])
+        );
+        match model.run(inputs(vec![1.0])) {
+            Err(NrtError::ShapeMismatch { tensor, .. }) => assert_eq!(tensor, "input0"),
+            other => panic!("Expected a shape mismatch, got {:?}", other),
+        }
+
+        let crashing_path =
+            std::env::temp_dir().join(format!("xla_crashing_{}.neff", std::process::id()));
+        std::fs::write(&crashing_path, CRASHING_NEFF).unwrap();
+        match runner.load(&crashing_path) {
+            Err(NrtError::RuntimeCrashed { signal, stderr }) => {
+                assert_eq!(signal, Some(libc::SIGSEGV), "{}", stderr);
+            }
+            other => panic!("Expected the worker to crash, got {:?}", other.err()),
+        }
+        std::fs::remove_file(&crashing_path).unwrap();
+
+        // The next call starts a new worker, and the model is loaded in it again.
+        let results = model.run(inputs(vec![3.0, 4.0])).unwrap();
+        assert_eq!(
+            results.output["output0"],
+            HostTensor::from_vec(vec![3f32, 4.0])
+        );
+        model.unload().unwrap();
+
+        let results = runner
+            .run_trn(
+                NEFF_PATH,
+                "isolated_test",
+                &["input0"],
+                vec![vec![5f32, 6.0].into()],
+                vec![vec![2]],
+            )
+            .unwrap();
+        assert_eq!(
+            results.output["output0"],
+            HostTensor::from_vec(vec![5f32, 6.0])
+        );
+    }
+
+    #[test]
+    fn isolated_runner_survives_a_crashing_run() {
+        let runner = runner("isolated_worker");
+        let inputs = |values: Vec<f32>| {
+            HashMap::from([("input0".to_string(), HostTensor::from_vec(values))])
+        };
+
+        let mut model = runner.load(NEFF_PATH).unwrap();
+        match model.run(inputs(CRASHING_INPUT.to_vec())) {
+            Err(NrtError::RuntimeCrashed { signal, stderr }) => {
+                assert_eq!(signal, Some(libc::SIGSEGV), "{}", stderr);
+            }
+            other => panic!("Expected the worker to crash, got {:?}", other.err()),
+        }
+        let results = model.run(inputs(vec![1.0, 2.0])).unwrap();
+        assert_eq!(
+            results.output["output0"],
+            HostTensor::from_vec(vec![1f32, 2.0])
+        );
+        model.unload().unwrap();
+
+        // Lengths beyond what a response can hold are rejected before they are
+        // allocated.
+        let mut frame = Vec::new();
+        write_frame(&mut frame, &Response::Unloaded, &[&[0; 16]]).unwrap();
+        assert!(read_frame::<_, Response>(&mut frame.as_slice(), 16).is_ok());
+        let e = read_frame::<_, Response>(&mut frame.as_slice(), 15)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        let garbage = u64::MAX.to_le_bytes();
+        let e = read_frame::<_, Response>(&mut garbage.as_slice(), 0)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn isolated_runner_kills_a_worker_that_does_not_respond() {
+        let hanging = runner("hanging_worker");
+        hanging.set_timeout(Duration::from_millis(200));
+        match hanging.load(NEFF_PATH) {
+            Err(NrtError::RuntimeCrashed { signal, .. }) => {
+                assert_eq!(signal, Some(libc::SIGKILL))
+            }
+            other => panic!("Expected the worker to be killed, got {:?}", other.err()),
+        }
+
+        let runner = runner("isolated_worker");
+        let inputs = |values: [f32; 2]| {
+            HashMap::from([("input0".to_string(), HostTensor::from_vec(values.to_vec()))])
+        };
+        let mut model = runner.load(NEFF_PATH).unwrap();
+        runner.set_timeout(Duration::from_secs(1));
+        match model.run(inputs(HANGING_INPUT)) {
+            Err(NrtError::RuntimeCrashed { signal, .. }) => {
+                assert_eq!(signal, Some(libc::SIGKILL))
+            }
+            other => panic!("Expected the worker to be killed, got {:?}", other.err()),
+        }
+        runner.set_timeout(super::DEFAULT_TIMEOUT);
+        let results = model.run(inputs([1.0, 2.0])).unwrap();
+        assert_eq!(
+            results.output["output0"],
+            HostTensor::from_vec(vec![1f32, 2.0])
+        );
+    }
+
+    #[test]
+    fn a_worker_cannot_start_a_worker() {
+        assert!(matches!(
+            runner("nested_worker").load(NEFF_PATH),
+            Err(NrtError::NestedWorker)
+        ));
+    }
+}
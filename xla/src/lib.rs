@@ -1,3 +1,19 @@
+pub mod batch;
+pub mod bench;
 pub mod bindings;
+pub mod dtype;
+pub mod error;
+pub mod executor;
+pub mod fake;
+pub mod host_tensor;
+pub mod isolated;
+pub mod model;
+pub mod neff;
+pub mod pool;
+pub mod runtime;
+pub mod shared;
+pub mod tensor;
+#[cfg(test)]
+mod test_util;
 pub mod trn;
 pub mod xla_runner;
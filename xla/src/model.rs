@@ -0,0 +1,184 @@
+//! A model loaded onto NeuronCores that is unloaded when it is dropped.
+
+// System
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Third party
+use serde::{Deserialize, Serialize};
+
+// Local
+use crate::error::NrtError;
+use crate::neff::read_neff;
+use crate::runtime::{ModelHandle, NeuronRuntime, TensorInfo};
+use crate::tensor::TensorSet;
+
+/// Where a model is placed when it is loaded with `nrt_load`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadOptions {
+    /// The first NeuronCore the model is loaded onto. Its input and output tensors are
+    /// allocated on this core.
+    pub start_nc: i32,
+    /// The number of consecutive NeuronCores the model spans.
+    pub nc_count: i32,
+}
+
+impl Default for LoadOptions {
+    /// A single NeuronCore, the first one.
+    fn default() -> Self {
+        LoadOptions {
+            start_nc: 0,
+            nc_count: 1,
+        }
+    }
+}
+
+/// An owned `nrt_model_t`.
+///
+/// The model's tensor info is queried once when it is loaded, and `nrt_unload` is
+/// called when the `Model` is dropped, so a model can be loaded once and executed many
+/// times without leaking device memory.
+pub struct Model {
+    runtime: Arc<dyn NeuronRuntime>,
+    handle: ModelHandle,
+    options: LoadOptions,
+    tensor_info: Vec<TensorInfo>,
+    load_time: Duration,
+    tensor_info_time: Duration,
+    unloaded: bool,
+}
+
+impl Model {
+    /// Loads a model from the bytes of a NEFF onto the first NeuronCore.
+    pub fn from_bytes(runtime: Arc<dyn NeuronRuntime>, neff: &[u8]) -> Result<Self, NrtError> {
+        Self::from_bytes_with_options(runtime, neff, LoadOptions::default())
+    }
+
+    /// Loads a model from the bytes of a NEFF onto the NeuronCores given by `options`.
+    pub fn from_bytes_with_options(
+        runtime: Arc<dyn NeuronRuntime>,
+        neff: &[u8],
+        options: LoadOptions,
+    ) -> Result<Self, NrtError> {
+        let start = Instant::now();
+        let handle = runtime.load(neff, options.start_nc, options.nc_count)?;
+        let load_time = start.elapsed();
+
+        let mut model = Model {
+            runtime,
+            handle,
+            options,
+            tensor_info: Vec::new(),
+            load_time,
+            tensor_info_time: Duration::ZERO,
+            unloaded: false,
+        };
+        // If this fails the model is unloaded when it is dropped.
+        let start = Instant::now();
+        model.tensor_info = model.runtime.model_tensor_info(handle)?;
+        model.tensor_info_time = start.elapsed();
+        Ok(model)
+    }
+
+    /// Reads a NEFF file and loads it with [`Model::from_bytes`].
+    pub fn from_file<P: AsRef<Path>>(
+        runtime: Arc<dyn NeuronRuntime>,
+        neff_path: P,
+    ) -> Result<Self, NrtError> {
+        let neff_data = read_neff(neff_path.as_ref())?;
+        Self::from_bytes(runtime, &neff_data)
+    }
+
+    /// The runtime the model is loaded on.
+    pub fn runtime(&self) -> &Arc<dyn NeuronRuntime> {
+        &self.runtime
+    }
+
+    /// The raw handle of the model.
+    pub fn handle(&self) -> ModelHandle {
+        self.handle
+    }
+
+    /// The NeuronCores the model was loaded onto.
+    pub fn options(&self) -> LoadOptions {
+        self.options
+    }
+
+    /// The model's input and output tensors, as reported by `nrt_get_model_tensor_info`.
+    pub fn tensor_info(&self) -> &[TensorInfo] {
+        &self.tensor_info
+    }
+
+    /// How long `nrt_load` took.
+    pub fn load_time(&self) -> Duration {
+        self.load_time
+    }
+
+    /// How long querying the tensor info took.
+    pub fn tensor_info_time(&self) -> Duration {
+        self.tensor_info_time
+    }
+
+    /// Runs the model on the given input tensors, writing into the given output tensors.
+    pub fn execute(&self, inputs: &TensorSet, outputs: &TensorSet) -> Result<(), NrtError> {
+        self.runtime
+            .execute(self.handle, inputs.handle(), outputs.handle())
+    }
+
+    /// Unloads the model now, returning any error from `nrt_unload` that dropping the
+    /// model would ignore.
+    pub fn unload(mut self) -> Result<(), NrtError> {
+        self.unloaded = true;
+        self.runtime.unload(self.handle)
+    }
+}
+
+impl Drop for Model {
+    fn drop(&mut self) {
+        if !self.unloaded {
+            // There is no way to report the error from here; use `Model::unload` to see it.
+            let _ = self.runtime.unload(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::sync::Arc;
+
+    // Local
+    use super::Model;
+    use crate::bindings::nrt;
+    use crate::error::{NrtError, NrtStatus};
+    use crate::fake::FakeRuntime;
+    use crate::runtime::NeuronRuntime;
+    use crate::test_util::{f32_tensor, NEFF_PATH};
+
+    #[test]
+    fn model_is_unloaded_on_drop() {
+        let runtime = Arc::new(FakeRuntime::new(vec![f32_tensor(
+            "output0",
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            vec![1],
+        )]));
+        runtime.init().unwrap();
+
+        let model = Model::from_file(runtime.clone(), NEFF_PATH).unwrap();
+        assert_eq!(model.tensor_info().len(), 1);
+        assert_eq!(runtime.loaded_model_count(), 1);
+        drop(model);
+        assert_eq!(runtime.loaded_model_count(), 0);
+
+        // A failed tensor info query must not leak the loaded model.
+        runtime.fail_next("nrt_get_model_tensor_info", NrtStatus::Failure);
+        assert!(Model::from_bytes(runtime.clone(), b"neff").is_err());
+        assert_eq!(runtime.loaded_model_count(), 0);
+
+        assert!(matches!(
+            Model::from_file(runtime.clone(), "./missing.neff"),
+            Err(NrtError::Io { .. })
+        ));
+    }
+}
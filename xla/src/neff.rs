@@ -0,0 +1,600 @@
+//! Reads NEFF files without libnrt.
+//!
+//! A NEFF starts with a fixed size binary header, followed by a gzip compressed tar
+//! archive of the compiled graph: JSON metadata such as `neff.json` and
+//! `sg00/def.json`, and the instruction streams and weights of each subgraph.
+//!
+//! `neff.json` is a TVM graph whose arguments and heads are the inputs and outputs of
+//! the model, so the model's tensor info can be read without loading it with libnrt.
+
+// System
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+// Third party
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+
+// Local
+use crate::bindings::nrt;
+use crate::dtype::{dtype_name, dtype_size};
+use crate::error::{IoOperation, NrtError};
+use crate::runtime::TensorInfo;
+
+/// The size of the fields this module reads from the start of the header.
+const MIN_HEADER_SIZE: usize = 0x100;
+const VERSION_OFFSET: usize = 0x0;
+const HEADER_SIZE_OFFSET: usize = 0x8;
+const DATA_SIZE_OFFSET: usize = 0x10;
+const DATA_MD5_OFFSET: usize = 0xac;
+const UUID_OFFSET: usize = 0xcc;
+const FILENAME_OFFSET: usize = 0xdc;
+
+/// The archive member holding the model graph.
+const GRAPH_MEMBER: &str = "neff.json";
+/// The file name of the definition of each subgraph, e.g. `sg00/def.json`.
+const DEFINITION_MEMBER: &str = "def.json";
+
+/// The fixed binary header at the start of a NEFF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeffHeader {
+    /// The NEFF format version.
+    pub version: u64,
+    /// The size of the header in bytes. The archive starts right after it.
+    pub header_size: u64,
+    /// The size of the archive in bytes.
+    pub data_size: u64,
+    /// The MD5 digest of the archive.
+    pub data_md5: [u8; 16],
+    /// The UUID the compiler assigned to the NEFF.
+    pub uuid: [u8; 16],
+    /// The path the compiler wrote the NEFF to.
+    pub original_filename: String,
+}
+
+impl NeffHeader {
+    /// Parses the header at the start of `neff`.
+    pub fn parse(neff: &[u8]) -> Result<Self, NrtError> {
+        if neff.len() < MIN_HEADER_SIZE {
+            return Err(invalid_neff(format!(
+                "{} bytes is too short for a NEFF header",
+                neff.len()
+            )));
+        }
+        let u64_at = |offset: usize| u64::from_le_bytes(array_at(neff, offset));
+
+        let header_size = u64_at(HEADER_SIZE_OFFSET);
+        if header_size < MIN_HEADER_SIZE as u64 || header_size > neff.len() as u64 {
+            return Err(invalid_neff(format!(
+                "header size {} is invalid for a {} byte file",
+                header_size,
+                neff.len()
+            )));
+        }
+
+        // The filename is NUL terminated and padded to the end of the header.
+        let filename = &neff[FILENAME_OFFSET..header_size as usize];
+        let filename_len = filename
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(filename.len());
+
+        Ok(NeffHeader {
+            version: u64_at(VERSION_OFFSET),
+            header_size,
+            data_size: u64_at(DATA_SIZE_OFFSET),
+            data_md5: array_at(neff, DATA_MD5_OFFSET),
+            uuid: array_at(neff, UUID_OFFSET),
+            original_filename: String::from_utf8_lossy(&filename[..filename_len]).into_owned(),
+        })
+    }
+
+    /// The UUID in its usual hyphenated form.
+    pub fn uuid_string(&self) -> String {
+        let hex: String = self
+            .uuid
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+}
+
+/// A file in the archive of a NEFF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeffMember {
+    /// The path of the file within the archive, e.g. `sg00/def.json`.
+    pub name: String,
+    /// The uncompressed size of the file in bytes.
+    pub size: u64,
+}
+
+/// The header and the archive contents of a NEFF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NeffInfo {
+    pub header: NeffHeader,
+    /// The files in the archive, in archive order.
+    pub members: Vec<NeffMember>,
+    /// The inputs and outputs of the model; see [`tensor_info`].
+    pub tensors: Vec<TensorInfo>,
+}
+
+impl NeffInfo {
+    /// Parses a NEFF, checking that its archive is complete and matches the digest in
+    /// the header.
+    pub fn from_bytes(neff: &[u8]) -> Result<Self, NrtError> {
+        let header = NeffHeader::parse(neff)?;
+        let mut members = Vec::new();
+        let mut graph = None;
+        for_each_member(neff, &header, |entry| {
+            let name = entry_name(entry)?;
+            if name == GRAPH_MEMBER {
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf).map_err(archive_error)?;
+                graph = Some(buf);
+            }
+            members.push(NeffMember {
+                name,
+                size: entry.header().size().map_err(archive_error)?,
+            });
+            Ok(true)
+        })?;
+        let graph = graph.ok_or_else(|| missing_member(GRAPH_MEMBER))?;
+        Ok(NeffInfo {
+            header,
+            members,
+            tensors: parse_tensor_info(&graph)?,
+        })
+    }
+
+    /// Reads and parses a NEFF file.
+    pub fn from_file<P: AsRef<Path>>(neff_path: P) -> Result<Self, NrtError> {
+        Self::from_bytes(&read_neff(neff_path.as_ref())?)
+    }
+
+    /// Looks up a file in the archive by its path.
+    pub fn member(&self, name: &str) -> Option<&NeffMember> {
+        self.members.iter().find(|member| member.name == name)
+    }
+}
+
+/// Reads a NEFF file into memory.
+pub(crate) fn read_neff(neff_path: &Path) -> Result<Vec<u8>, NrtError> {
+    std::fs::read(neff_path).map_err(|e| NrtError::Io {
+        operation: IoOperation::Read,
+        path: neff_path.to_path_buf(),
+        source: Arc::new(e),
+    })
+}
+
+/// Checks the archive of `neff` against `header` and calls `f` on each file in it until
+/// `f` returns `false`.
+fn for_each_member<F>(neff: &[u8], header: &NeffHeader, mut f: F) -> Result<(), NrtError>
+where
+    F: FnMut(&mut tar::Entry<'_, GzDecoder<&[u8]>>) -> Result<bool, NrtError>,
+{
+    let data = &neff[header.header_size as usize..];
+    if data.len() as u64 != header.data_size {
+        return Err(invalid_neff(format!(
+            "the header gives an archive size of {} bytes but {} bytes follow it",
+            header.data_size,
+            data.len()
+        )));
+    }
+    if md5::compute(data).0 != header.data_md5 {
+        return Err(invalid_neff(
+            "the archive does not match the digest in the header".to_string(),
+        ));
+    }
+
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    for entry in archive.entries().map_err(archive_error)? {
+        let mut entry = entry.map_err(archive_error)?;
+        if entry.header().entry_type().is_file() && !f(&mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the file `name` from the archive of `neff`.
+pub fn read_member(neff: &[u8], name: &str) -> Result<Vec<u8>, NrtError> {
+    let header = NeffHeader::parse(neff)?;
+    let mut contents = None;
+    for_each_member(neff, &header, |entry| {
+        if entry_name(entry)? != name {
+            return Ok(true);
+        }
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).map_err(archive_error)?;
+        contents = Some(buf);
+        Ok(false)
+    })?;
+    contents.ok_or_else(|| missing_member(name))
+}
+
+/// The input and output tensors of the model in `neff`, read from its `neff.json`.
+///
+/// This gives the same tensor info as `nrt_get_model_tensor_info` without loading the
+/// model: the inputs in argument order followed by the outputs in graph order.
+pub fn tensor_info(neff: &[u8]) -> Result<Vec<TensorInfo>, NrtError> {
+    parse_tensor_info(&read_member(neff, GRAPH_MEMBER)?)
+}
+
+/// An estimate of the device memory a NEFF needs once it is loaded, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MemoryEstimate {
+    /// The constants the model is loaded with.
+    pub weights: u64,
+    /// The input and output tensors.
+    pub io: u64,
+    /// The intermediate values, which are placed in a scratchpad.
+    pub scratch: u64,
+    /// The instruction streams and lookup tables of the engines.
+    pub instructions: u64,
+}
+
+impl MemoryEstimate {
+    /// The total number of bytes, saturating at `u64::MAX`.
+    pub fn total(&self) -> u64 {
+        self.weights
+            .saturating_add(self.io)
+            .saturating_add(self.scratch)
+            .saturating_add(self.instructions)
+    }
+}
+
+/// Estimates the device memory `neff` needs from the variables each subgraph declares
+/// in its `def.json`, without loading it.
+///
+/// The scratchpad of a subgraph is as large as the furthest extent of the variables
+/// placed in it. Subgraphs are assumed not to share any memory.
+pub fn estimate_memory(neff: &[u8]) -> Result<MemoryEstimate, NrtError> {
+    let header = NeffHeader::parse(neff)?;
+    let mut definitions = Vec::new();
+    let mut binaries = Vec::new();
+    for_each_member(neff, &header, |entry| {
+        let name = entry_name(entry)?;
+        let dir = name
+            .strip_suffix(DEFINITION_MEMBER)
+            .filter(|dir| dir.is_empty() || dir.ends_with('/'));
+        if let Some(dir) = dir {
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf).map_err(archive_error)?;
+            definitions.push((dir.to_string(), buf));
+        } else if name.ends_with(".bin") {
+            binaries.push((name, entry.header().size().map_err(archive_error)?));
+        }
+        Ok(true)
+    })?;
+
+    let mut estimate = MemoryEstimate::default();
+    for (dir, definition) in &definitions {
+        let definition: Definition = serde_json::from_slice(definition)
+            .map_err(|e| invalid_neff(format!("{}{} is invalid: {}", dir, DEFINITION_MEMBER, e)))?;
+        let too_large = || invalid_neff(format!("{}{} is too large", dir, DEFINITION_MEMBER));
+        let add = |total: &mut u64, size: u64| -> Result<(), NrtError> {
+            *total = total.checked_add(size).ok_or_else(too_large)?;
+            Ok(())
+        };
+        let mut scratch_end = 0;
+        for variable in definition.var.values() {
+            match variable.kind.as_str() {
+                "file" => add(&mut estimate.weights, variable.size)?,
+                "input" | "output" => add(&mut estimate.io, variable.size)?,
+                "virtual" if variable.backing_variable.is_none() => {
+                    let mut end = variable.backing_variable_off.unwrap_or(0);
+                    add(&mut end, variable.size)?;
+                    scratch_end = scratch_end.max(end);
+                }
+                _ => {}
+            }
+        }
+        add(&mut estimate.scratch, scratch_end)?;
+        for (name, size) in &binaries {
+            // Only the files next to the def.json, so a root-level one does not also
+            // count those of every subgraph.
+            let parent = name.rfind('/').map_or("", |end| &name[..=end]);
+            if parent == dir {
+                add(&mut estimate.instructions, *size)?;
+            }
+        }
+    }
+    [estimate.io, estimate.scratch, estimate.instructions]
+        .into_iter()
+        .try_fold(estimate.weights, u64::checked_add)
+        .ok_or_else(|| invalid_neff("the model is too large".to_string()))?;
+    Ok(estimate)
+}
+
+/// The variables of a subgraph in its `def.json`.
+#[derive(Deserialize)]
+struct Definition {
+    var: HashMap<String, Variable>,
+}
+
+#[derive(Deserialize)]
+struct Variable {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    size: u64,
+    /// The variable a virtual variable is placed in, if it is not the scratchpad.
+    backing_variable: Option<String>,
+    backing_variable_off: Option<u64>,
+}
+
+/// The parts of the TVM graph in `neff.json` that describe its inputs and outputs.
+#[derive(Deserialize)]
+struct Graph {
+    nodes: Vec<GraphNode>,
+    /// The nodes that are inputs of the graph.
+    arg_nodes: Vec<usize>,
+    /// The outputs of the graph as `[node, output index, version]`.
+    heads: Vec<Vec<usize>>,
+    /// The index of the first output of each node in the attribute lists.
+    node_row_ptr: Vec<usize>,
+    attrs: GraphAttrs,
+}
+
+#[derive(Deserialize)]
+struct GraphNode {
+    name: String,
+    /// The names of the outputs of a compiled subgraph.
+    #[serde(default)]
+    output_names: Vec<String>,
+}
+
+/// Typed lists, stored as `[type name, values]`.
+#[derive(Deserialize)]
+struct GraphAttrs {
+    shape: (String, Vec<Vec<u32>>),
+    dltype: (String, Vec<String>),
+}
+
+fn parse_tensor_info(graph: &[u8]) -> Result<Vec<TensorInfo>, NrtError> {
+    let graph: Graph = serde_json::from_slice(graph)
+        .map_err(|e| invalid_neff(format!("{} is invalid: {}", GRAPH_MEMBER, e)))?;
+
+    let inputs = graph
+        .arg_nodes
+        .iter()
+        .map(|&node| (node, 0, nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT));
+    let outputs = graph.heads.iter().map(|head| {
+        let node = head
+            .first()
+            .ok_or_else(|| invalid_neff(format!("{} has an empty head", GRAPH_MEMBER)))?;
+        Ok((
+            *node,
+            head.get(1).copied().unwrap_or(0),
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+        ))
+    });
+    inputs
+        .map(Ok)
+        .chain(outputs)
+        .map(|tensor| {
+            let (node, index, usage) = tensor?;
+            graph_tensor_info(&graph, node, index, usage)
+        })
+        .collect()
+}
+
+/// The tensor info of output `index` of `node`.
+fn graph_tensor_info(
+    graph: &Graph,
+    node: usize,
+    index: usize,
+    usage: nrt::nrt_tensor_usage_t,
+) -> Result<TensorInfo, NrtError> {
+    let missing = || {
+        invalid_neff(format!(
+            "{} has no output {} of node {}",
+            GRAPH_MEMBER, index, node
+        ))
+    };
+    let graph_node = graph.nodes.get(node).ok_or_else(missing)?;
+    let name = if usage == nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT {
+        graph_node.name.clone()
+    } else {
+        graph_node
+            .output_names
+            .get(index)
+            .ok_or_else(missing)?
+            .clone()
+    };
+
+    let entry = graph
+        .node_row_ptr
+        .get(node)
+        .ok_or_else(missing)?
+        .checked_add(index)
+        .ok_or_else(missing)?;
+    let shape = graph.attrs.shape.1.get(entry).ok_or_else(missing)?.clone();
+    let dltype = graph.attrs.dltype.1.get(entry).ok_or_else(missing)?;
+    let dtype = graph_dtype(dltype);
+    let element_size = dtype_size(dtype)
+        .ok_or_else(|| invalid_neff(format!("tensor {} has unsupported dtype {}", name, dltype)))?;
+    let size = shape
+        .iter()
+        .try_fold(element_size, |size, &dim| size.checked_mul(dim as usize))
+        .ok_or_else(|| invalid_neff(format!("tensor {} is too large", name)))?;
+    Ok(TensorInfo {
+        size,
+        name,
+        usage,
+        dtype,
+        shape,
+    })
+}
+
+/// The `nrt_dtype_t` of a TVM dtype name, e.g. `float32`.
+fn graph_dtype(dltype: &str) -> nrt::nrt_dtype_t {
+    let dtypes = [
+        nrt::nrt_dtype_NRT_DTYPE_FLOAT32,
+        nrt::nrt_dtype_NRT_DTYPE_FLOAT16,
+        nrt::nrt_dtype_NRT_DTYPE_BFLOAT16,
+        nrt::nrt_dtype_NRT_DTYPE_INT8,
+        nrt::nrt_dtype_NRT_DTYPE_UINT8,
+        nrt::nrt_dtype_NRT_DTYPE_INT16,
+        nrt::nrt_dtype_NRT_DTYPE_UINT16,
+        nrt::nrt_dtype_NRT_DTYPE_INT32,
+        nrt::nrt_dtype_NRT_DTYPE_UINT32,
+        nrt::nrt_dtype_NRT_DTYPE_INT64,
+        nrt::nrt_dtype_NRT_DTYPE_UINT64,
+    ];
+    match dltype {
+        "float8_e3m4" => nrt::nrt_dtype_NRT_DTYPE_FP8_E3,
+        "float8_e4m3" | "float8_e4m3fn" => nrt::nrt_dtype_NRT_DTYPE_FP8_E4,
+        "float8_e5m2" => nrt::nrt_dtype_NRT_DTYPE_FP8_E5,
+        _ => dtypes
+            .into_iter()
+            .find(|&dtype| dtype_name(dtype).eq_ignore_ascii_case(dltype))
+            .unwrap_or(nrt::nrt_dtype_NRT_DTYPE_UNKNOWN),
+    }
+}
+
+fn entry_name<R: Read>(entry: &tar::Entry<'_, R>) -> Result<String, NrtError> {
+    let path = entry.path().map_err(archive_error)?;
+    Ok(path.to_string_lossy().trim_start_matches("./").to_string())
+}
+
+fn array_at<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
+
+fn invalid_neff(reason: String) -> NrtError {
+    NrtError::InvalidNeff { reason }
+}
+
+fn missing_member(name: &str) -> NrtError {
+    invalid_neff(format!("the archive has no file {}", name))
+}
+
+fn archive_error(e: std::io::Error) -> NrtError {
+    invalid_neff(format!("the archive is corrupt: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    // Local
+    use super::{
+        estimate_memory, parse_tensor_info, read_member, tensor_info, MemoryEstimate, NeffInfo,
+    };
+    use crate::bindings::nrt;
+    use crate::error::NrtError;
+    use crate::runtime::TensorInfo;
+
+    const NEFF_PATH: &str = "./transformer_xla_working.neff";
+
+    #[test]
+    fn parses_the_transformer_neff() {
+        let info = NeffInfo::from_file(NEFF_PATH).unwrap();
+        assert_eq!(info.header.version, 2);
+        assert_eq!(info.header.header_size, 0x400);
+        assert_eq!(info.header.data_size, 1696039);
+        assert_eq!(
+            info.header.uuid_string(),
+            "972b6394-eeb7-11ed-84c3-979090e48521"
+        );
+        assert!(info
+            .header
+            .original_filename
+            .starts_with("/tmp/transformer_xla_benchmark_"));
+        assert_eq!(info.members[0].name, "neff.json");
+        assert_eq!(info.member("sg00/def.json").unwrap().size, 322834);
+
+        let neff = std::fs::read(NEFF_PATH).unwrap();
+        let info_json = read_member(&neff, "info.json").unwrap();
+        assert_eq!(info_json.len(), 928);
+
+        // The transformer has no inputs and two outputs of the same size.
+        let output = |name: &str, shape: Vec<u32>| TensorInfo {
+            name: name.to_string(),
+            usage: nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            size: 6 * 4096 * 4,
+            dtype: nrt::nrt_dtype_NRT_DTYPE_FLOAT32,
+            shape,
+        };
+        let expected = vec![
+            output("output1", vec![6, 8, 512]),
+            output("output0", vec![6, 8, 4, 128]),
+        ];
+        assert_eq!(info.tensors, expected);
+        assert_eq!(tensor_info(&neff).unwrap(), expected);
+
+        let estimate = estimate_memory(&neff).unwrap();
+        assert_eq!(
+            estimate,
+            MemoryEstimate {
+                weights: 128 * 128 * 4,
+                io: 2 * 6 * 4096 * 4,
+                scratch: 390819840,
+                instructions: 7208896,
+            }
+        );
+        assert_eq!(estimate.total(), 65536 + 196608 + 390819840 + 7208896);
+        let huge = MemoryEstimate {
+            weights: u64::MAX,
+            ..estimate
+        };
+        assert_eq!(huge.total(), u64::MAX);
+
+        // A truncated or corrupted archive is rejected.
+        assert!(matches!(
+            NeffInfo::from_bytes(&neff[..neff.len() - 1]),
+            Err(NrtError::InvalidNeff { .. })
+        ));
+        let mut corrupt = neff.clone();
+        corrupt[2000] ^= 0xff;
+        assert!(matches!(
+            NeffInfo::from_bytes(&corrupt),
+            Err(NrtError::InvalidNeff { .. })
+        ));
+        assert!(matches!(
+            NeffInfo::from_bytes(b"neff"),
+            Err(NrtError::InvalidNeff { .. })
+        ));
+
+        // So is a graph with an empty head or a tensor too large to address.
+        let graph = |heads: &str, shape: &str| {
+            format!(
+                r#"{{"nodes": [{{"name": "sg00", "output_names": ["output0"]}}],
+                    "arg_nodes": [], "heads": {}, "node_row_ptr": [0, 1],
+                    "attrs": {{"shape": ["list_shape", [{}]],
+                               "dltype": ["list_str", ["float32"]]}}}}"#,
+                heads, shape
+            )
+        };
+        assert_eq!(
+            parse_tensor_info(graph("[[0, 0, 0]]", "[2, 3]").as_bytes()).unwrap()[0].size,
+            24
+        );
+        assert!(matches!(
+            parse_tensor_info(graph("[[]]", "[2, 3]").as_bytes()),
+            Err(NrtError::InvalidNeff { .. })
+        ));
+        assert!(matches!(
+            parse_tensor_info(graph("[[0, 0, 0]]", "[4294967295, 4294967295, 4]").as_bytes()),
+            Err(NrtError::InvalidNeff { .. })
+        ));
+
+        // A row pointer past the end of the attributes cannot wrap around to an entry.
+        let wrapping = r#"{"nodes": [{"name": "sg00", "output_names": ["output0", "output1"]}],
+            "arg_nodes": [], "heads": [[0, 1, 0]], "node_row_ptr": [18446744073709551615, 1],
+            "attrs": {"shape": ["list_shape", [[2, 3]]], "dltype": ["list_str", ["float32"]]}}"#;
+        assert!(matches!(
+            parse_tensor_info(wrapping.as_bytes()),
+            Err(NrtError::InvalidNeff { .. })
+        ));
+    }
+}
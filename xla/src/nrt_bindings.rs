@@ -0,0 +1,342 @@
+/* automatically generated by rust-bindgen 0.64.0 */
+
+#[repr(C)]
+#[derive(Default)]
+pub struct __IncompleteArrayField<T>(::std::marker::PhantomData<T>, [T; 0]);
+impl<T> __IncompleteArrayField<T> {
+    #[inline]
+    pub const fn new() -> Self {
+        __IncompleteArrayField(::std::marker::PhantomData, [])
+    }
+    #[inline]
+    pub fn as_ptr(&self) -> *const T {
+        self as *const _ as *const T
+    }
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self as *mut _ as *mut T
+    }
+    #[inline]
+    pub unsafe fn as_slice(&self, len: usize) -> &[T] {
+        ::std::slice::from_raw_parts(self.as_ptr(), len)
+    }
+    #[inline]
+    pub unsafe fn as_mut_slice(&mut self, len: usize) -> &mut [T] {
+        ::std::slice::from_raw_parts_mut(self.as_mut_ptr(), len)
+    }
+}
+impl<T> ::std::fmt::Debug for __IncompleteArrayField<T> {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        fmt.write_str("__IncompleteArrayField")
+    }
+}
+pub type NRT_STATUS = ::std::os::raw::c_uint;
+pub const NRT_STATUS_NRT_SUCCESS: NRT_STATUS = 0;
+pub const NRT_STATUS_NRT_FAILURE: NRT_STATUS = 1;
+pub const NRT_STATUS_NRT_INVALID: NRT_STATUS = 2;
+pub const NRT_STATUS_NRT_INVALID_HANDLE: NRT_STATUS = 3;
+pub const NRT_STATUS_NRT_RESOURCE: NRT_STATUS = 4;
+pub const NRT_STATUS_NRT_TIMEOUT: NRT_STATUS = 5;
+pub const NRT_STATUS_NRT_HW_ERROR: NRT_STATUS = 6;
+pub const NRT_STATUS_NRT_QUEUE_FULL: NRT_STATUS = 7;
+pub const NRT_STATUS_NRT_LOAD_NOT_ENOUGH_NC: NRT_STATUS = 9;
+pub const NRT_STATUS_NRT_UNSUPPORTED_NEFF_VERSION: NRT_STATUS = 10;
+pub const NRT_STATUS_NRT_FAIL_HOST_MEM_ALLOC: NRT_STATUS = 11;
+pub const NRT_STATUS_NRT_UNINITIALIZED: NRT_STATUS = 13;
+pub const NRT_STATUS_NRT_CLOSED: NRT_STATUS = 14;
+pub const NRT_STATUS_NRT_EXEC_BAD_INPUT: NRT_STATUS = 1002;
+pub const NRT_STATUS_NRT_EXEC_COMPLETED_WITH_NUM_ERR: NRT_STATUS = 1003;
+pub const NRT_STATUS_NRT_EXEC_COMPLETED_WITH_ERR: NRT_STATUS = 1004;
+pub const NRT_STATUS_NRT_EXEC_NC_BUSY: NRT_STATUS = 1005;
+pub const NRT_STATUS_NRT_EXEC_OOB: NRT_STATUS = 1006;
+pub const NRT_STATUS_NRT_COLL_PENDING: NRT_STATUS = 1100;
+pub const NRT_STATUS_NRT_EXEC_HW_ERR_COLLECTIVES: NRT_STATUS = 1200;
+pub const NRT_STATUS_NRT_EXEC_HW_ERR_HBM_UE: NRT_STATUS = 1201;
+pub const NRT_STATUS_NRT_EXEC_HW_ERR_NC_UE: NRT_STATUS = 1202;
+pub const NRT_STATUS_NRT_EXEC_HW_ERR_DMA_ABORT: NRT_STATUS = 1203;
+pub const NRT_STATUS_NRT_EXEC_SW_NQ_OVERFLOW: NRT_STATUS = 1204;
+pub const nrt_tensor_placement_t_NRT_TENSOR_PLACEMENT_DEVICE: nrt_tensor_placement_t = 0;
+pub const nrt_tensor_placement_t_NRT_TENSOR_PLACEMENT_HOST: nrt_tensor_placement_t = 1;
+pub const nrt_tensor_placement_t_NRT_TENSOR_PLACEMENT_VIRTUAL: nrt_tensor_placement_t = 2;
+pub type nrt_tensor_placement_t = ::std::os::raw::c_uint;
+pub const nrt_framework_type_t_NRT_FRAMEWORK_TYPE_INVALID: nrt_framework_type_t = 0;
+pub const nrt_framework_type_t_NRT_FRAMEWORK_TYPE_NO_FW: nrt_framework_type_t = 1;
+pub const nrt_framework_type_t_NRT_FRAMEWORK_TYPE_TENSORFLOW: nrt_framework_type_t = 2;
+pub const nrt_framework_type_t_NRT_FRAMEWORK_TYPE_PYTORCH: nrt_framework_type_t = 3;
+pub const nrt_framework_type_t_NRT_FRAMEWORK_TYPE_MXNET: nrt_framework_type_t = 4;
+pub type nrt_framework_type_t = ::std::os::raw::c_uint;
+pub const nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT: nrt_tensor_usage = 0;
+pub const nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT: nrt_tensor_usage = 1;
+pub type nrt_tensor_usage = ::std::os::raw::c_uint;
+pub use self::nrt_tensor_usage as nrt_tensor_usage_t;
+pub const nrt_dtype_NRT_DTYPE_UNKNOWN: nrt_dtype = 0;
+pub const nrt_dtype_NRT_DTYPE_FLOAT32: nrt_dtype = 1;
+pub const nrt_dtype_NRT_DTYPE_FLOAT16: nrt_dtype = 2;
+pub const nrt_dtype_NRT_DTYPE_BFLOAT16: nrt_dtype = 3;
+pub const nrt_dtype_NRT_DTYPE_INT8: nrt_dtype = 4;
+pub const nrt_dtype_NRT_DTYPE_UINT8: nrt_dtype = 5;
+pub const nrt_dtype_NRT_DTYPE_INT16: nrt_dtype = 6;
+pub const nrt_dtype_NRT_DTYPE_UINT16: nrt_dtype = 7;
+pub const nrt_dtype_NRT_DTYPE_INT32: nrt_dtype = 8;
+pub const nrt_dtype_NRT_DTYPE_UINT32: nrt_dtype = 9;
+pub const nrt_dtype_NRT_DTYPE_INT64: nrt_dtype = 10;
+pub const nrt_dtype_NRT_DTYPE_UINT64: nrt_dtype = 11;
+pub const nrt_dtype_NRT_DTYPE_FP8_E3: nrt_dtype = 12;
+pub const nrt_dtype_NRT_DTYPE_FP8_E4: nrt_dtype = 13;
+pub const nrt_dtype_NRT_DTYPE_FP8_E5: nrt_dtype = 14;
+pub type nrt_dtype = ::std::os::raw::c_uint;
+pub use self::nrt_dtype as nrt_dtype_t;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct nrt_model {
+    _unused: [u8; 0],
+}
+pub type nrt_model_t = nrt_model;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct nrt_tensor {
+    _unused: [u8; 0],
+}
+pub type nrt_tensor_t = nrt_tensor;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct nrt_tensor_set {
+    _unused: [u8; 0],
+}
+pub type nrt_tensor_set_t = nrt_tensor_set;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct nrt_tensor_info {
+    pub name: [::std::os::raw::c_char; 256usize],
+    pub usage: nrt_tensor_usage_t,
+    pub size: usize,
+    pub dtype: nrt_dtype_t,
+    pub shape: *mut u32,
+    pub ndim: u32,
+}
+pub type nrt_tensor_info_t = nrt_tensor_info;
+#[repr(C)]
+#[derive(Debug)]
+pub struct nrt_tensor_info_array {
+    pub tensor_count: u64,
+    pub tensor_array: __IncompleteArrayField<nrt_tensor_info_t>,
+}
+pub type nrt_tensor_info_array_t = nrt_tensor_info_array;
+extern crate libloading;
+pub struct Nrt {
+    __library: ::libloading::Library,
+    pub nrt_init: unsafe extern "C" fn(
+        framework: nrt_framework_type_t,
+        fw_version: *const ::std::os::raw::c_char,
+        fal_version: *const ::std::os::raw::c_char,
+    ) -> NRT_STATUS,
+    pub nrt_close: unsafe extern "C" fn(),
+    pub nrt_load: unsafe extern "C" fn(
+        neff_bytes: *const ::std::os::raw::c_void,
+        size: usize,
+        start_nc: i32,
+        nc_count: i32,
+        model: *mut *mut nrt_model_t,
+    ) -> NRT_STATUS,
+    pub nrt_unload: unsafe extern "C" fn(model: *mut nrt_model_t) -> NRT_STATUS,
+    pub nrt_get_model_tensor_info: unsafe extern "C" fn(
+        model: *mut nrt_model_t,
+        tensor_info: *mut *mut nrt_tensor_info_array_t,
+    ) -> NRT_STATUS,
+    pub nrt_free_model_tensor_info:
+        unsafe extern "C" fn(tensor_info: *mut nrt_tensor_info_array_t) -> NRT_STATUS,
+    pub nrt_execute: unsafe extern "C" fn(
+        model: *mut nrt_model_t,
+        input_set: *const nrt_tensor_set_t,
+        output_set: *mut nrt_tensor_set_t,
+    ) -> NRT_STATUS,
+    pub nrt_tensor_allocate: unsafe extern "C" fn(
+        tensor_placement: nrt_tensor_placement_t,
+        logical_nc_id: ::std::os::raw::c_int,
+        size: usize,
+        name: *const ::std::os::raw::c_char,
+        tensor: *mut *mut nrt_tensor_t,
+    ) -> NRT_STATUS,
+    pub nrt_tensor_free: unsafe extern "C" fn(tensor: *mut *mut nrt_tensor_t),
+    pub nrt_tensor_read: unsafe extern "C" fn(
+        tensor: *const nrt_tensor_t,
+        buf: *mut ::std::os::raw::c_void,
+        offset: usize,
+        size: usize,
+    ) -> NRT_STATUS,
+    pub nrt_tensor_write: unsafe extern "C" fn(
+        tensor: *mut nrt_tensor_t,
+        buf: *const ::std::os::raw::c_void,
+        offset: usize,
+        size: usize,
+    ) -> NRT_STATUS,
+    pub nrt_allocate_tensor_set:
+        unsafe extern "C" fn(result: *mut *mut nrt_tensor_set_t) -> NRT_STATUS,
+    pub nrt_destroy_tensor_set: unsafe extern "C" fn(tensor_set: *mut *mut nrt_tensor_set_t),
+    pub nrt_add_tensor_to_tensor_set: unsafe extern "C" fn(
+        tensor_set: *mut nrt_tensor_set_t,
+        tensor_name: *const ::std::os::raw::c_char,
+        tensor: *mut nrt_tensor_t,
+    ) -> NRT_STATUS,
+    pub nrt_get_tensor_from_tensor_set: unsafe extern "C" fn(
+        tensor_set: *mut nrt_tensor_set_t,
+        tensor_name: *const ::std::os::raw::c_char,
+        tensor: *mut *mut nrt_tensor_t,
+    ) -> NRT_STATUS,
+}
+impl Nrt {
+    pub unsafe fn new<P>(path: P) -> Result<Self, ::libloading::Error>
+    where
+        P: AsRef<::std::ffi::OsStr>,
+    {
+        let library = ::libloading::Library::new(path)?;
+        Self::from_library(library)
+    }
+    pub unsafe fn from_library<L>(library: L) -> Result<Self, ::libloading::Error>
+    where
+        L: Into<::libloading::Library>,
+    {
+        let __library = library.into();
+        let nrt_init = __library.get(b"nrt_init\0").map(|sym| *sym)?;
+        let nrt_close = __library.get(b"nrt_close\0").map(|sym| *sym)?;
+        let nrt_load = __library.get(b"nrt_load\0").map(|sym| *sym)?;
+        let nrt_unload = __library.get(b"nrt_unload\0").map(|sym| *sym)?;
+        let nrt_get_model_tensor_info = __library
+            .get(b"nrt_get_model_tensor_info\0")
+            .map(|sym| *sym)?;
+        let nrt_free_model_tensor_info = __library
+            .get(b"nrt_free_model_tensor_info\0")
+            .map(|sym| *sym)?;
+        let nrt_execute = __library.get(b"nrt_execute\0").map(|sym| *sym)?;
+        let nrt_tensor_allocate = __library.get(b"nrt_tensor_allocate\0").map(|sym| *sym)?;
+        let nrt_tensor_free = __library.get(b"nrt_tensor_free\0").map(|sym| *sym)?;
+        let nrt_tensor_read = __library.get(b"nrt_tensor_read\0").map(|sym| *sym)?;
+        let nrt_tensor_write = __library.get(b"nrt_tensor_write\0").map(|sym| *sym)?;
+        let nrt_allocate_tensor_set = __library
+            .get(b"nrt_allocate_tensor_set\0")
+            .map(|sym| *sym)?;
+        let nrt_destroy_tensor_set = __library.get(b"nrt_destroy_tensor_set\0").map(|sym| *sym)?;
+        let nrt_add_tensor_to_tensor_set = __library
+            .get(b"nrt_add_tensor_to_tensor_set\0")
+            .map(|sym| *sym)?;
+        let nrt_get_tensor_from_tensor_set = __library
+            .get(b"nrt_get_tensor_from_tensor_set\0")
+            .map(|sym| *sym)?;
+        Ok(Nrt {
+            __library,
+            nrt_init,
+            nrt_close,
+            nrt_load,
+            nrt_unload,
+            nrt_get_model_tensor_info,
+            nrt_free_model_tensor_info,
+            nrt_execute,
+            nrt_tensor_allocate,
+            nrt_tensor_free,
+            nrt_tensor_read,
+            nrt_tensor_write,
+            nrt_allocate_tensor_set,
+            nrt_destroy_tensor_set,
+            nrt_add_tensor_to_tensor_set,
+            nrt_get_tensor_from_tensor_set,
+        })
+    }
+    pub unsafe fn nrt_init(
+        &self,
+        framework: nrt_framework_type_t,
+        fw_version: *const ::std::os::raw::c_char,
+        fal_version: *const ::std::os::raw::c_char,
+    ) -> NRT_STATUS {
+        (self.nrt_init)(framework, fw_version, fal_version)
+    }
+    pub unsafe fn nrt_close(&self) {
+        (self.nrt_close)()
+    }
+    pub unsafe fn nrt_load(
+        &self,
+        neff_bytes: *const ::std::os::raw::c_void,
+        size: usize,
+        start_nc: i32,
+        nc_count: i32,
+        model: *mut *mut nrt_model_t,
+    ) -> NRT_STATUS {
+        (self.nrt_load)(neff_bytes, size, start_nc, nc_count, model)
+    }
+    pub unsafe fn nrt_unload(&self, model: *mut nrt_model_t) -> NRT_STATUS {
+        (self.nrt_unload)(model)
+    }
+    pub unsafe fn nrt_get_model_tensor_info(
+        &self,
+        model: *mut nrt_model_t,
+        tensor_info: *mut *mut nrt_tensor_info_array_t,
+    ) -> NRT_STATUS {
+        (self.nrt_get_model_tensor_info)(model, tensor_info)
+    }
+    pub unsafe fn nrt_free_model_tensor_info(
+        &self,
+        tensor_info: *mut nrt_tensor_info_array_t,
+    ) -> NRT_STATUS {
+        (self.nrt_free_model_tensor_info)(tensor_info)
+    }
+    pub unsafe fn nrt_execute(
+        &self,
+        model: *mut nrt_model_t,
+        input_set: *const nrt_tensor_set_t,
+        output_set: *mut nrt_tensor_set_t,
+    ) -> NRT_STATUS {
+        (self.nrt_execute)(model, input_set, output_set)
+    }
+    pub unsafe fn nrt_tensor_allocate(
+        &self,
+        tensor_placement: nrt_tensor_placement_t,
+        logical_nc_id: ::std::os::raw::c_int,
+        size: usize,
+        name: *const ::std::os::raw::c_char,
+        tensor: *mut *mut nrt_tensor_t,
+    ) -> NRT_STATUS {
+        (self.nrt_tensor_allocate)(tensor_placement, logical_nc_id, size, name, tensor)
+    }
+    pub unsafe fn nrt_tensor_free(&self, tensor: *mut *mut nrt_tensor_t) {
+        (self.nrt_tensor_free)(tensor)
+    }
+    pub unsafe fn nrt_tensor_read(
+        &self,
+        tensor: *const nrt_tensor_t,
+        buf: *mut ::std::os::raw::c_void,
+        offset: usize,
+        size: usize,
+    ) -> NRT_STATUS {
+        (self.nrt_tensor_read)(tensor, buf, offset, size)
+    }
+    pub unsafe fn nrt_tensor_write(
+        &self,
+        tensor: *mut nrt_tensor_t,
+        buf: *const ::std::os::raw::c_void,
+        offset: usize,
+        size: usize,
+    ) -> NRT_STATUS {
+        (self.nrt_tensor_write)(tensor, buf, offset, size)
+    }
+    pub unsafe fn nrt_allocate_tensor_set(&self, result: *mut *mut nrt_tensor_set_t) -> NRT_STATUS {
+        (self.nrt_allocate_tensor_set)(result)
+    }
+    pub unsafe fn nrt_destroy_tensor_set(&self, tensor_set: *mut *mut nrt_tensor_set_t) {
+        (self.nrt_destroy_tensor_set)(tensor_set)
+    }
+    pub unsafe fn nrt_add_tensor_to_tensor_set(
+        &self,
+        tensor_set: *mut nrt_tensor_set_t,
+        tensor_name: *const ::std::os::raw::c_char,
+        tensor: *mut nrt_tensor_t,
+    ) -> NRT_STATUS {
+        (self.nrt_add_tensor_to_tensor_set)(tensor_set, tensor_name, tensor)
+    }
+    pub unsafe fn nrt_get_tensor_from_tensor_set(
+        &self,
+        tensor_set: *mut nrt_tensor_set_t,
+        tensor_name: *const ::std::os::raw::c_char,
+        tensor: *mut *mut nrt_tensor_t,
+    ) -> NRT_STATUS {
+        (self.nrt_get_tensor_from_tensor_set)(tensor_set, tensor_name, tensor)
+    }
+}
@@ -0,0 +1,280 @@
+//! Replicas of one model on several NeuronCores, for serving runs in parallel.
+
+// System
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+// Local
+use crate::error::{NrtError, NrtStatus};
+use crate::host_tensor::HostTensor;
+use crate::model::LoadOptions;
+use crate::xla_runner::{LoadedModel, XLARunResults, XLARunner};
+
+/// The activity of one replica of a [`ModelPool`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaStats {
+    /// The NeuronCores the replica is loaded onto.
+    pub options: LoadOptions,
+    /// The number of runs dispatched to the replica that have not finished.
+    pub in_flight: usize,
+    /// The number of finished runs, including failed ones.
+    pub runs: u64,
+    /// The number of runs that returned an error.
+    pub failures: u64,
+    /// The total graph execution time of the successful runs; see
+    /// [`XLARunResults::runtime`].
+    pub execute_time: Duration,
+}
+
+struct Dispatch {
+    stats: Vec<ReplicaStats>,
+    /// The replica to consider first for the next run, so that ties between equally
+    /// busy replicas are broken round-robin.
+    next: usize,
+}
+
+/// The same NEFF loaded once per placement, with each run dispatched to the replica
+/// with the fewest runs in flight.
+///
+/// [`ModelPool::run`] takes `&self`, so one pool can be shared by many threads. Each
+/// replica runs one request at a time.
+pub struct ModelPool {
+    replicas: Vec<Mutex<LoadedModel>>,
+    dispatch: Mutex<Dispatch>,
+}
+
+impl ModelPool {
+    /// Loads `replicas` copies of the NEFF, each onto its own NeuronCore starting from
+    /// core 0.
+    pub fn load<P: AsRef<Path>>(
+        runner: &XLARunner,
+        neff_path: P,
+        replicas: usize,
+    ) -> Result<Self, NrtError> {
+        let replicas = i32::try_from(replicas)
+            .map_err(|_| NrtError::status("nrt_load", NrtStatus::Invalid))?;
+        let placements: Vec<_> = (0..replicas)
+            .map(|start_nc| LoadOptions {
+                start_nc,
+                nc_count: 1,
+            })
+            .collect();
+        Self::with_placements(runner, neff_path, &placements)
+    }
+
+    /// Loads one copy of the NEFF per entry of `placements`. At least one placement
+    /// must be given. If any load fails, the replicas loaded so far are unloaded.
+    pub fn with_placements<P: AsRef<Path>>(
+        runner: &XLARunner,
+        neff_path: P,
+        placements: &[LoadOptions],
+    ) -> Result<Self, NrtError> {
+        if placements.is_empty() {
+            return Err(NrtError::status("nrt_load", NrtStatus::Invalid));
+        }
+        let replicas = placements
+            .iter()
+            .map(|&options| {
+                runner
+                    .load_with_options(neff_path.as_ref(), options)
+                    .map(Mutex::new)
+            })
+            .collect::<Result<_, _>>()?;
+        let stats = placements
+            .iter()
+            .map(|&options| ReplicaStats {
+                options,
+                in_flight: 0,
+                runs: 0,
+                failures: 0,
+                execute_time: Duration::ZERO,
+            })
+            .collect();
+        Ok(ModelPool {
+            replicas,
+            dispatch: Mutex::new(Dispatch { stats, next: 0 }),
+        })
+    }
+
+    /// The number of replicas.
+    pub fn len(&self) -> usize {
+        self.replicas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// Runs the model on the least busy replica, like [`LoadedModel::run`].
+    pub fn run(&self, inputs: HashMap<String, HostTensor>) -> Result<XLARunResults, NrtError> {
+        let in_flight = self.acquire();
+        let result = lock(&self.replicas[in_flight.replica]).run(inputs);
+        in_flight.finish(&result);
+        result
+    }
+
+    /// A snapshot of the activity of each replica, in placement order.
+    pub fn stats(&self) -> Vec<ReplicaStats> {
+        lock(&self.dispatch).stats.clone()
+    }
+
+    /// Picks the replica with the fewest runs in flight and counts the new run.
+    fn acquire(&self) -> InFlight<'_> {
+        let mut dispatch = lock(&self.dispatch);
+        let count = dispatch.stats.len();
+        let replica = (0..count)
+            .map(|offset| (dispatch.next + offset) % count)
+            .min_by_key(|&replica| dispatch.stats[replica].in_flight)
+            .expect("a pool has at least one replica");
+        dispatch.stats[replica].in_flight += 1;
+        dispatch.next = (replica + 1) % count;
+        InFlight {
+            dispatch: &self.dispatch,
+            replica,
+        }
+    }
+}
+
+/// A run dispatched to a replica. It stops counting as in flight when dropped, so a
+/// run that panics does not keep its replica busy forever.
+struct InFlight<'a> {
+    dispatch: &'a Mutex<Dispatch>,
+    replica: usize,
+}
+
+impl InFlight<'_> {
+    /// Counts the finished run and its result.
+    fn finish(self, result: &Result<XLARunResults, NrtError>) {
+        let mut dispatch = lock(self.dispatch);
+        let stats = &mut dispatch.stats[self.replica];
+        stats.runs += 1;
+        match result {
+            Ok(results) => stats.execute_time += results.runtime,
+            Err(_) => stats.failures += 1,
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        lock(self.dispatch).stats[self.replica].in_flight -= 1;
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A replica that panicked mid-run still has valid tensors, and the stats are only
+    // updated under the lock.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::collections::HashMap;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::Duration;
+
+    // Local
+    use super::ModelPool;
+    use crate::error::NrtStatus;
+    use crate::fake::{FakeKernel, FakeRuntime};
+    use crate::host_tensor::HostTensor;
+    use crate::model::LoadOptions;
+    use crate::test_util::{echo_model, NEFF_PATH};
+    use crate::xla_runner::XLARunner;
+
+    #[test]
+    fn pool_dispatches_to_the_least_busy_replica() {
+        // Runs whose input is negative block until the gate is opened.
+        let gate = Arc::new((Mutex::new(false), Condvar::new()));
+        let kernel_gate = gate.clone();
+        let kernel: FakeKernel = Arc::new(move |inputs, outputs| {
+            if f32::from_ne_bytes(inputs[0][..4].try_into().unwrap()) < 0.0 {
+                let (open, opened) = &*kernel_gate;
+                let _open = opened
+                    .wait_while(open.lock().unwrap(), |open| !*open)
+                    .unwrap();
+            }
+            outputs[0].copy_from_slice(&inputs[0]);
+        });
+        let runtime = Arc::new(
+            FakeRuntime::new(echo_model(vec![1]))
+                .with_kernel(kernel)
+                .with_nc_count(3),
+        );
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+
+        // Placements must fit in an `i32`, and there is no fourth core to load a fourth
+        // replica on.
+        match ModelPool::load(&runner, NEFF_PATH, usize::MAX) {
+            Err(e) => assert_eq!(e.nrt_status(), Some(NrtStatus::Invalid)),
+            Ok(_) => panic!("Expected too many replicas to be rejected"),
+        }
+        match ModelPool::load(&runner, NEFF_PATH, 4) {
+            Err(e) => assert_eq!(e.nrt_status(), Some(NrtStatus::LoadNotEnoughNc)),
+            Ok(_) => panic!("Expected the fourth replica not to fit"),
+        }
+        assert_eq!(runtime.loaded_model_count(), 0);
+
+        let pool = ModelPool::load(&runner, NEFF_PATH, 3).unwrap();
+        assert_eq!(pool.len(), 3);
+        assert_eq!(
+            runtime.loaded_model_placements(),
+            (0..3)
+                .map(|start_nc| LoadOptions {
+                    start_nc,
+                    nc_count: 1
+                })
+                .collect::<Vec<_>>()
+        );
+        let inputs =
+            |value: f32| HashMap::from([("input0".to_string(), HostTensor::from_vec(vec![value]))]);
+
+        std::thread::scope(|scope| {
+            let blocked = scope.spawn(|| pool.run(inputs(-1.0)).unwrap());
+            while pool.stats()[0].in_flight == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            // The busy first replica is skipped, and the others take turns.
+            for i in 0..4 {
+                let results = pool.run(inputs(i as f32)).unwrap();
+                assert_eq!(
+                    results.output["output0"],
+                    HostTensor::from_vec(vec![i as f32])
+                );
+            }
+            let stats = pool.stats();
+            assert_eq!(stats[0].in_flight, 1);
+            assert_eq!(stats[0].runs, 0);
+            assert_eq!(stats[1].runs, 2);
+            assert_eq!(stats[2].runs, 2);
+
+            *gate.0.lock().unwrap() = true;
+            gate.1.notify_all();
+            blocked.join().unwrap();
+        });
+        let stats = pool.stats();
+        assert_eq!(stats[0].in_flight, 0);
+        assert_eq!(stats[0].runs, 1);
+
+        runtime.fail_next("nrt_execute", NrtStatus::ExecNcBusy);
+        assert!(pool.run(inputs(1.0)).is_err());
+        let failures: u64 = pool.stats().iter().map(|stats| stats.failures).sum();
+        assert_eq!(failures, 1);
+
+        // A run that panics no longer counts as in flight.
+        let panicked = std::panic::catch_unwind(|| {
+            let _in_flight = pool.acquire();
+            panic!("the run panicked");
+        });
+        assert!(panicked.is_err());
+        assert!(pool.stats().iter().all(|stats| stats.in_flight == 0));
+
+        drop(pool);
+        assert_eq!(runtime.loaded_model_count(), 0);
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+    }
+}
@@ -0,0 +1,409 @@
+//! A pluggable backend for the Neuron runtime (NRT).
+//!
+//! `XLARunner` and the helpers in `trn` only talk to the runtime through the
+//! [`NeuronRuntime`] trait. [`NrtRuntime`] forwards every call to libnrt, while
+//! [`crate::fake::FakeRuntime`] keeps everything in host memory so the crate can be
+//! exercised on machines without a Trainium device.
+
+// System
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::os::raw::c_void;
+
+// Third party
+use serde::{Deserialize, Serialize};
+
+// Local
+use crate::bindings::nrt;
+use crate::error::{NrtError, NrtStatus};
+
+/// An opaque handle to a model loaded by a [`NeuronRuntime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelHandle(pub usize);
+
+/// An opaque handle to a tensor allocated by a [`NeuronRuntime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TensorHandle(pub usize);
+
+/// An opaque handle to a tensor set allocated by a [`NeuronRuntime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TensorSetHandle(pub usize);
+
+/// An owned copy of an `nrt_tensor_info_t`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TensorInfo {
+    pub name: String,
+    pub usage: nrt::nrt_tensor_usage_t,
+    /// The size of the tensor in bytes.
+    pub size: usize,
+    pub dtype: nrt::nrt_dtype_t,
+    pub shape: Vec<u32>,
+}
+
+/// The subset of the NRT API used by this crate.
+///
+/// Every method mirrors the NRT function of the same name, with raw pointers
+/// replaced by handles and Rust slices. Failures are reported as [`NrtError::Status`]
+/// naming the NRT call that failed.
+pub trait NeuronRuntime: Send + Sync {
+    /// See `nrt_init`.
+    fn init(&self) -> Result<(), NrtError>;
+
+    /// See `nrt_close`.
+    fn close(&self);
+
+    /// See `nrt_load`.
+    fn load(&self, neff: &[u8], start_nc: i32, nc_count: i32) -> Result<ModelHandle, NrtError>;
+
+    /// See `nrt_unload`.
+    fn unload(&self, model: ModelHandle) -> Result<(), NrtError>;
+
+    /// See `nrt_get_model_tensor_info`. The returned infos are owned, so there is
+    /// no `nrt_free_model_tensor_info` counterpart.
+    fn model_tensor_info(&self, model: ModelHandle) -> Result<Vec<TensorInfo>, NrtError>;
+
+    /// See `nrt_tensor_allocate`.
+    fn tensor_allocate(
+        &self,
+        placement: nrt::nrt_tensor_placement_t,
+        logical_nc_id: i32,
+        size: usize,
+        name: &str,
+    ) -> Result<TensorHandle, NrtError>;
+
+    /// See `nrt_tensor_free`.
+    fn tensor_free(&self, tensor: TensorHandle);
+
+    /// See `nrt_tensor_read`. Reads `buf.len()` bytes starting at `offset`.
+    fn tensor_read(
+        &self,
+        tensor: TensorHandle,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> Result<(), NrtError>;
+
+    /// See `nrt_tensor_write`. Writes `buf.len()` bytes starting at `offset`.
+    fn tensor_write(&self, tensor: TensorHandle, buf: &[u8], offset: usize)
+        -> Result<(), NrtError>;
+
+    /// See `nrt_allocate_tensor_set`.
+    fn allocate_tensor_set(&self) -> Result<TensorSetHandle, NrtError>;
+
+    /// See `nrt_destroy_tensor_set`. This does not free the tensors in the set.
+    fn destroy_tensor_set(&self, tensor_set: TensorSetHandle);
+
+    /// See `nrt_add_tensor_to_tensor_set`.
+    fn add_tensor_to_tensor_set(
+        &self,
+        tensor_set: TensorSetHandle,
+        name: &str,
+        tensor: TensorHandle,
+    ) -> Result<(), NrtError>;
+
+    /// See `nrt_get_tensor_from_tensor_set`.
+    fn get_tensor_from_tensor_set(
+        &self,
+        tensor_set: TensorSetHandle,
+        name: &str,
+    ) -> Result<TensorHandle, NrtError>;
+
+    /// See `nrt_execute`.
+    fn execute(
+        &self,
+        model: ModelHandle,
+        inputs: TensorSetHandle,
+        outputs: TensorSetHandle,
+    ) -> Result<(), NrtError>;
+}
+
+/// Converts a tensor name into the NUL terminated string NRT expects.
+fn tensor_name(call: &'static str, name: &str) -> Result<CString, NrtError> {
+    CString::new(name).map_err(|_| NrtError::status(call, NrtStatus::Invalid).with_tensor(name))
+}
+
+/// The environment variable that overrides where libnrt is loaded from.
+pub const NRT_LIBRARY_PATH_ENV: &str = "NRT_LIBRARY_PATH";
+
+/// The library loaded when [`NRT_LIBRARY_PATH_ENV`] is not set. It is resolved through the
+/// usual dynamic linker search path, e.g. `LD_LIBRARY_PATH=/opt/aws/neuron/lib`.
+pub const DEFAULT_NRT_LIBRARY: &str = "libnrt.so.1";
+
+/// The [`NeuronRuntime`] backed by libnrt.
+///
+/// libnrt is opened at runtime rather than linked, so binaries built with this crate
+/// start on machines without the Neuron SDK and only fail when a runtime is created.
+pub struct NrtRuntime {
+    nrt: nrt::Nrt,
+}
+
+impl NrtRuntime {
+    /// Loads libnrt from [`NRT_LIBRARY_PATH_ENV`] if it is set, otherwise from
+    /// [`DEFAULT_NRT_LIBRARY`].
+    pub fn from_env() -> Result<Self, NrtError> {
+        Self::open(Self::library_path_from_env())
+    }
+
+    /// The path [`NrtRuntime::from_env`] loads libnrt from.
+    pub fn library_path_from_env() -> OsString {
+        std::env::var_os(NRT_LIBRARY_PATH_ENV).unwrap_or_else(|| DEFAULT_NRT_LIBRARY.into())
+    }
+
+    /// Loads libnrt from the given path and resolves every NRT symbol this crate uses.
+    pub fn open<P: AsRef<OsStr>>(path: P) -> Result<Self, NrtError> {
+        let path = path.as_ref();
+        // Loading a library runs its initializers, and the symbols are resolved with the
+        // signatures from the NRT headers the bindings were generated from.
+        let nrt = unsafe { nrt::Nrt::new(path) }.map_err(|e| NrtError::Library {
+            path: path.to_string_lossy().into_owned(),
+            message: e.to_string(),
+        })?;
+        Ok(NrtRuntime { nrt })
+    }
+}
+
+impl NeuronRuntime for NrtRuntime {
+    fn init(&self) -> Result<(), NrtError> {
+        NrtError::check("nrt_init", unsafe {
+            self.nrt.nrt_init(
+                nrt::nrt_framework_type_t_NRT_FRAMEWORK_TYPE_NO_FW,
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        })
+    }
+
+    fn close(&self) {
+        unsafe {
+            self.nrt.nrt_close();
+        }
+    }
+
+    fn load(&self, neff: &[u8], start_nc: i32, nc_count: i32) -> Result<ModelHandle, NrtError> {
+        let mut model: *mut nrt::nrt_model_t = std::ptr::null_mut();
+        NrtError::check("nrt_load", unsafe {
+            self.nrt.nrt_load(
+                neff.as_ptr() as *const _,
+                neff.len(),
+                start_nc,
+                nc_count,
+                &mut model as *mut *mut nrt::nrt_model_t,
+            )
+        })?;
+        if model.is_null() {
+            return Err(NrtError::status("nrt_load", NrtStatus::InvalidHandle));
+        }
+        Ok(ModelHandle(model as usize))
+    }
+
+    fn unload(&self, model: ModelHandle) -> Result<(), NrtError> {
+        NrtError::check("nrt_unload", unsafe {
+            self.nrt.nrt_unload(model.0 as *mut nrt::nrt_model_t)
+        })
+    }
+
+    fn model_tensor_info(&self, model: ModelHandle) -> Result<Vec<TensorInfo>, NrtError> {
+        let mut info_array: *mut nrt::nrt_tensor_info_array_t = std::ptr::null_mut();
+        NrtError::check("nrt_get_model_tensor_info", unsafe {
+            self.nrt.nrt_get_model_tensor_info(
+                model.0 as *mut nrt::nrt_model_t,
+                &mut info_array as *mut *mut nrt::nrt_tensor_info_array_t,
+            )
+        })?;
+        if info_array.is_null() {
+            return Err(NrtError::status(
+                "nrt_get_model_tensor_info",
+                NrtStatus::InvalidHandle,
+            ));
+        }
+
+        let tensor_count = unsafe { (*info_array).tensor_count } as usize;
+        let tensor_info_array = unsafe { (*info_array).tensor_array.as_ptr() };
+        let infos = (0..tensor_count)
+            .map(|tensor_idx| {
+                let tensor_info = unsafe { &*tensor_info_array.add(tensor_idx) };
+                let name = unsafe { CStr::from_ptr(tensor_info.name.as_ptr()) };
+                let shape = if tensor_info.shape.is_null() {
+                    Vec::new()
+                } else {
+                    unsafe {
+                        std::slice::from_raw_parts(tensor_info.shape, tensor_info.ndim as usize)
+                    }
+                    .to_vec()
+                };
+                TensorInfo {
+                    name: name.to_string_lossy().into_owned(),
+                    usage: tensor_info.usage,
+                    size: tensor_info.size,
+                    dtype: tensor_info.dtype,
+                    shape,
+                }
+            })
+            .collect();
+
+        NrtError::check("nrt_free_model_tensor_info", unsafe {
+            self.nrt.nrt_free_model_tensor_info(info_array)
+        })?;
+        Ok(infos)
+    }
+
+    fn tensor_allocate(
+        &self,
+        placement: nrt::nrt_tensor_placement_t,
+        logical_nc_id: i32,
+        size: usize,
+        name: &str,
+    ) -> Result<TensorHandle, NrtError> {
+        let name = tensor_name("nrt_tensor_allocate", name)?;
+        let mut tensor: *mut nrt::nrt_tensor_t = std::ptr::null_mut();
+        NrtError::check("nrt_tensor_allocate", unsafe {
+            self.nrt.nrt_tensor_allocate(
+                placement,
+                logical_nc_id,
+                size,
+                name.as_ptr(),
+                &mut tensor as *mut *mut nrt::nrt_tensor_t,
+            )
+        })?;
+        if tensor.is_null() {
+            return Err(NrtError::status(
+                "nrt_tensor_allocate",
+                NrtStatus::InvalidHandle,
+            ));
+        }
+        Ok(TensorHandle(tensor as usize))
+    }
+
+    fn tensor_free(&self, tensor: TensorHandle) {
+        let mut tensor = tensor.0 as *mut nrt::nrt_tensor_t;
+        unsafe {
+            self.nrt
+                .nrt_tensor_free(&mut tensor as *mut *mut nrt::nrt_tensor_t)
+        };
+    }
+
+    fn tensor_read(
+        &self,
+        tensor: TensorHandle,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> Result<(), NrtError> {
+        NrtError::check("nrt_tensor_read", unsafe {
+            self.nrt.nrt_tensor_read(
+                tensor.0 as *const nrt::nrt_tensor_t,
+                buf.as_mut_ptr() as *mut c_void,
+                offset,
+                buf.len(),
+            )
+        })
+    }
+
+    fn tensor_write(
+        &self,
+        tensor: TensorHandle,
+        buf: &[u8],
+        offset: usize,
+    ) -> Result<(), NrtError> {
+        NrtError::check("nrt_tensor_write", unsafe {
+            self.nrt.nrt_tensor_write(
+                tensor.0 as *mut nrt::nrt_tensor_t,
+                buf.as_ptr() as *const c_void,
+                offset,
+                buf.len(),
+            )
+        })
+    }
+
+    fn allocate_tensor_set(&self) -> Result<TensorSetHandle, NrtError> {
+        let mut tensor_set: *mut nrt::nrt_tensor_set_t = std::ptr::null_mut();
+        NrtError::check("nrt_allocate_tensor_set", unsafe {
+            self.nrt
+                .nrt_allocate_tensor_set(&mut tensor_set as *mut *mut nrt::nrt_tensor_set_t)
+        })?;
+        if tensor_set.is_null() {
+            return Err(NrtError::status(
+                "nrt_allocate_tensor_set",
+                NrtStatus::InvalidHandle,
+            ));
+        }
+        Ok(TensorSetHandle(tensor_set as usize))
+    }
+
+    fn destroy_tensor_set(&self, tensor_set: TensorSetHandle) {
+        let mut tensor_set = tensor_set.0 as *mut nrt::nrt_tensor_set_t;
+        unsafe {
+            self.nrt
+                .nrt_destroy_tensor_set(&mut tensor_set as *mut *mut nrt::nrt_tensor_set_t)
+        };
+    }
+
+    fn add_tensor_to_tensor_set(
+        &self,
+        tensor_set: TensorSetHandle,
+        name: &str,
+        tensor: TensorHandle,
+    ) -> Result<(), NrtError> {
+        let name = tensor_name("nrt_add_tensor_to_tensor_set", name)?;
+        NrtError::check("nrt_add_tensor_to_tensor_set", unsafe {
+            self.nrt.nrt_add_tensor_to_tensor_set(
+                tensor_set.0 as *mut nrt::nrt_tensor_set_t,
+                name.as_ptr(),
+                tensor.0 as *mut nrt::nrt_tensor_t,
+            )
+        })
+    }
+
+    fn get_tensor_from_tensor_set(
+        &self,
+        tensor_set: TensorSetHandle,
+        name: &str,
+    ) -> Result<TensorHandle, NrtError> {
+        let name = tensor_name("nrt_get_tensor_from_tensor_set", name)?;
+        let mut tensor: *mut nrt::nrt_tensor_t = std::ptr::null_mut();
+        NrtError::check("nrt_get_tensor_from_tensor_set", unsafe {
+            self.nrt.nrt_get_tensor_from_tensor_set(
+                tensor_set.0 as *mut nrt::nrt_tensor_set_t,
+                name.as_ptr(),
+                &mut tensor as *mut *mut nrt::nrt_tensor_t,
+            )
+        })?;
+        if tensor.is_null() {
+            return Err(NrtError::status(
+                "nrt_get_tensor_from_tensor_set",
+                NrtStatus::InvalidHandle,
+            ));
+        }
+        Ok(TensorHandle(tensor as usize))
+    }
+
+    fn execute(
+        &self,
+        model: ModelHandle,
+        inputs: TensorSetHandle,
+        outputs: TensorSetHandle,
+    ) -> Result<(), NrtError> {
+        NrtError::check("nrt_execute", unsafe {
+            self.nrt.nrt_execute(
+                model.0 as *mut nrt::nrt_model_t,
+                inputs.0 as *const nrt::nrt_tensor_set_t,
+                outputs.0 as *mut nrt::nrt_tensor_set_t,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Local
+    use super::NrtRuntime;
+    use crate::error::NrtError;
+
+    #[test]
+    fn missing_nrt_library_is_an_error() {
+        let err = NrtRuntime::open("/nonexistent/libnrt.so.1")
+            .err()
+            .expect("Loading a missing library should fail");
+        match err {
+            NrtError::Library { ref path, .. } => assert_eq!(path, "/nonexistent/libnrt.so.1"),
+            _ => panic!("Unexpected error {}", err),
+        }
+    }
+}
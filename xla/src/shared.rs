@@ -0,0 +1,273 @@
+//! A runtime that is initialized once and shared by every runner in the process.
+//!
+//! libnrt must only be initialized once per process: `nrt_init` fails if it is called
+//! again, including after `nrt_close`. [`SharedRuntime`] calls `init` when the first
+//! [`RuntimeHandle`] is acquired and only calls `close` when it is explicitly requested
+//! or, for the libnrt runtime returned by [`nrt_runtime`], when the process exits
+//! without any handles left.
+
+// System
+use std::ffi::{OsStr, OsString};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+// Local
+use crate::error::NrtError;
+use crate::executor::{Executors, DEFAULT_QUEUE_DEPTH};
+use crate::runtime::{NeuronRuntime, NrtRuntime};
+
+#[derive(Default)]
+struct SharedState {
+    /// The number of live `RuntimeHandle`s.
+    handles: usize,
+    initialized: bool,
+    close_requested: bool,
+    closed: bool,
+}
+
+/// A [`NeuronRuntime`] with reference-counted initialization.
+pub struct SharedRuntime {
+    runtime: Arc<dyn NeuronRuntime>,
+    state: Mutex<SharedState>,
+    /// The NeuronCore worker threads of the runners on this runtime, while any runner or
+    /// model holds them.
+    executors: Mutex<Weak<Executors>>,
+}
+
+impl SharedRuntime {
+    /// Wraps an uninitialized runtime.
+    pub fn new(runtime: Arc<dyn NeuronRuntime>) -> Arc<Self> {
+        Arc::new(SharedRuntime {
+            runtime,
+            state: Mutex::new(SharedState::default()),
+            executors: Mutex::new(Weak::new()),
+        })
+    }
+
+    /// Returns a handle to the runtime, initializing it if this is the first handle.
+    ///
+    /// Returns [`NrtError::RuntimeClosed`] once [`SharedRuntime::close`] has been called.
+    pub fn acquire(self: &Arc<Self>) -> Result<RuntimeHandle, NrtError> {
+        let mut state = self.state();
+        if state.close_requested {
+            return Err(NrtError::RuntimeClosed);
+        }
+        // Initializing under the lock makes concurrent callers wait for the first one.
+        if !state.initialized {
+            self.runtime.init()?;
+            state.initialized = true;
+        }
+        state.handles += 1;
+        Ok(RuntimeHandle {
+            shared: self.clone(),
+        })
+    }
+
+    /// Closes the runtime once the last outstanding handle is dropped, or immediately if
+    /// there are none. The runtime cannot be acquired again afterwards.
+    pub fn close(&self) {
+        let mut state = self.state();
+        state.close_requested = true;
+        self.close_if_unused(&mut state);
+    }
+
+    /// Whether the runtime has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.state().closed
+    }
+
+    /// The number of live handles to the runtime.
+    pub fn handle_count(&self) -> usize {
+        self.state().handles
+    }
+
+    /// The NeuronCore worker threads shared by every runner on this runtime, created
+    /// again if every runner and model that held them was dropped.
+    pub(crate) fn executors(&self) -> Arc<Executors> {
+        let mut executors = self.executors.lock().unwrap_or_else(|e| e.into_inner());
+        executors.upgrade().unwrap_or_else(|| {
+            let created = Arc::new(Executors::new(DEFAULT_QUEUE_DEPTH));
+            *executors = Arc::downgrade(&created);
+            created
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, SharedState> {
+        // The state is updated atomically under the lock, so it is consistent even if a
+        // thread panicked while holding it.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close_if_unused(&self, state: &mut SharedState) {
+        if state.close_requested && state.handles == 0 && !state.closed {
+            if state.initialized {
+                self.runtime.close();
+            }
+            state.closed = true;
+        }
+    }
+
+    /// Closes the runtime if no handles are left. Skipped while handles are alive,
+    /// since threads that are still running, such as executor or batcher workers, may be
+    /// using it, and if another thread holds the lock, since the process is exiting
+    /// anyway.
+    fn close_at_exit(&self) {
+        if let Ok(mut state) = self.state.try_lock() {
+            if state.handles == 0 && state.initialized && !state.closed {
+                self.runtime.close();
+                state.closed = true;
+            }
+        }
+    }
+}
+
+/// A cheap, cloneable reference to an initialized [`SharedRuntime`].
+pub struct RuntimeHandle {
+    shared: Arc<SharedRuntime>,
+}
+
+impl RuntimeHandle {
+    /// The initialized runtime.
+    pub fn runtime(&self) -> &Arc<dyn NeuronRuntime> {
+        &self.shared.runtime
+    }
+
+    /// The shared runtime this handle was acquired from.
+    pub fn shared(&self) -> &Arc<SharedRuntime> {
+        &self.shared
+    }
+}
+
+impl Clone for RuntimeHandle {
+    fn clone(&self) -> Self {
+        self.shared.state().handles += 1;
+        RuntimeHandle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for RuntimeHandle {
+    fn drop(&mut self) {
+        let mut state = self.shared.state();
+        state.handles -= 1;
+        self.shared.close_if_unused(&mut state);
+    }
+}
+
+/// The libnrt runtime and the path it was loaded from, once it has been loaded.
+static NRT: Mutex<Option<(OsString, Arc<SharedRuntime>)>> = Mutex::new(None);
+
+extern "C" fn close_nrt_at_exit() {
+    // Skipped if another thread is loading libnrt, like `close_at_exit`.
+    if let Ok(nrt) = NRT.try_lock() {
+        if let Some((_, shared)) = nrt.as_ref() {
+            shared.close_at_exit();
+        }
+    }
+}
+
+/// The process-wide libnrt runtime.
+///
+/// libnrt is loaded the first time this is called, from `path` if one is given and
+/// otherwise from [`NrtRuntime::library_path_from_env`]. It is closed when the process
+/// exits if nothing is using it anymore. Asking for a different path after libnrt has
+/// been loaded is an error. If loading fails, the next call tries again.
+pub fn nrt_runtime(path: Option<&OsStr>) -> Result<Arc<SharedRuntime>, NrtError> {
+    let mut nrt = NRT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((loaded_path, shared)) = nrt.as_ref() {
+        return match path {
+            Some(path) if path != loaded_path => Err(NrtError::Library {
+                path: path.to_string_lossy().into_owned(),
+                message: format!(
+                    "libnrt was already loaded from {}",
+                    loaded_path.to_string_lossy()
+                ),
+            }),
+            _ => Ok(shared.clone()),
+        };
+    }
+    let path = path
+        .map(OsStr::to_os_string)
+        .unwrap_or_else(NrtRuntime::library_path_from_env);
+    let shared = SharedRuntime::new(Arc::new(NrtRuntime::open(&path)?));
+    // If registration fails the runtime is simply left open at exit.
+    unsafe { libc::atexit(close_nrt_at_exit) };
+    *nrt = Some((path, shared.clone()));
+    Ok(shared)
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    // Local
+    use super::{nrt_runtime, SharedRuntime};
+    use crate::error::NrtError;
+    use crate::fake::FakeRuntime;
+
+    #[test]
+    fn shared_runtime_initializes_once_and_closes_on_request() {
+        let fake = Arc::new(FakeRuntime::new(Vec::new()));
+        let shared = SharedRuntime::new(fake.clone());
+
+        // The fake fails a second nrt_init, so this only succeeds if init runs once.
+        let handles: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| shared.acquire().unwrap()))
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        assert!(fake.is_initialized());
+        assert_eq!(shared.handle_count(), 8);
+
+        let extra = handles[0].clone();
+        drop(handles);
+        assert_eq!(shared.handle_count(), 1);
+        assert!(fake.is_initialized());
+
+        // Closing waits for the last handle.
+        shared.close();
+        assert!(!shared.is_closed());
+        assert!(matches!(shared.acquire(), Err(NrtError::RuntimeClosed)));
+        drop(extra);
+        assert!(shared.is_closed());
+        assert!(!fake.is_initialized());
+    }
+
+    #[test]
+    fn shared_runtime_is_only_closed_at_exit_when_unused() {
+        let fake = Arc::new(FakeRuntime::new(Vec::new()));
+        let shared = SharedRuntime::new(fake.clone());
+        let handle = shared.acquire().unwrap();
+        shared.close_at_exit();
+        assert!(fake.is_initialized());
+        drop(handle);
+        shared.close_at_exit();
+        assert!(shared.is_closed());
+        assert!(!fake.is_initialized());
+    }
+
+    #[test]
+    #[cfg_attr(
+        feature = "trn",
+        ignore = "the device tests load libnrt in this process"
+    )]
+    fn nrt_runtime_retries_a_failed_load() {
+        let missing = OsStr::new("/nonexistent/libnrt.so.1");
+        for _ in 0..2 {
+            assert!(matches!(
+                nrt_runtime(Some(missing)),
+                Err(NrtError::Library { path, .. }) if path == "/nonexistent/libnrt.so.1"
+            ));
+        }
+        // A different path is tried rather than reported as already loaded.
+        match nrt_runtime(Some(OsStr::new("/nonexistent/other.so"))) {
+            Err(NrtError::Library { message, .. }) => {
+                assert!(!message.contains("already loaded"), "{}", message)
+            }
+            other => panic!("Expected libnrt to fail to load, got {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Owned tensors and tensor sets that free their runtime resources when dropped.
+
+// System
+use std::sync::Arc;
+
+// Local
+use crate::bindings::nrt;
+use crate::error::NrtError;
+use crate::runtime::{NeuronRuntime, TensorHandle, TensorInfo, TensorSetHandle};
+
+/// A plain data type that can be copied to and from tensor memory byte for byte.
+///
+/// # Safety
+///
+/// Implementors must have no padding and no invalid bit patterns, so that any
+/// sequence of `size_of::<Self>()` bytes is a valid value.
+pub unsafe trait Element: Copy + 'static {}
+
+unsafe impl Element for u8 {}
+unsafe impl Element for i8 {}
+unsafe impl Element for u16 {}
+unsafe impl Element for i16 {}
+unsafe impl Element for u32 {}
+unsafe impl Element for i32 {}
+unsafe impl Element for u64 {}
+unsafe impl Element for i64 {}
+unsafe impl Element for f32 {}
+unsafe impl Element for f64 {}
+unsafe impl Element for half::f16 {}
+unsafe impl Element for half::bf16 {}
+
+/// Views a slice of elements as its bytes.
+pub fn as_bytes<T: Element>(data: &[T]) -> &[u8] {
+    // Elements have no padding, so every byte of the slice is initialized.
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+/// Views a mutable slice of elements as its bytes.
+pub fn as_bytes_mut<T: Element>(data: &mut [T]) -> &mut [u8] {
+    // Any bytes written through the view form valid elements.
+    unsafe {
+        std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, std::mem::size_of_val(data))
+    }
+}
+
+/// A borrowed value for the tensor `name`, written to the device without being copied
+/// on the host first.
+#[derive(Debug, Clone, Copy)]
+pub struct TensorRef<'a> {
+    pub name: &'a str,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> TensorRef<'a> {
+    /// Borrows `data` as the value of the tensor `name`. Only its size in bytes is
+    /// checked against the tensor, so raw bytes can be given for any dtype.
+    pub fn new<T: Element>(name: &'a str, data: &'a [T]) -> Self {
+        TensorRef {
+            name,
+            bytes: as_bytes(data),
+        }
+    }
+}
+
+/// A caller-owned buffer the tensor `name` is read into.
+#[derive(Debug)]
+pub struct TensorMut<'a> {
+    pub name: &'a str,
+    pub bytes: &'a mut [u8],
+}
+
+impl<'a> TensorMut<'a> {
+    /// Borrows `data` to read the tensor `name` into. It must be exactly as large as
+    /// the tensor.
+    pub fn new<T: Element>(name: &'a str, data: &'a mut [T]) -> Self {
+        TensorMut {
+            name,
+            bytes: as_bytes_mut(data),
+        }
+    }
+}
+
+/// An owned `nrt_tensor_t`, freed with `nrt_tensor_free` when dropped.
+pub struct Tensor {
+    runtime: Arc<dyn NeuronRuntime>,
+    handle: TensorHandle,
+    name: String,
+    size: usize,
+}
+
+impl Tensor {
+    /// Allocates `size` bytes of device memory for the tensor `name` on the NeuronCore
+    /// `logical_nc_id`.
+    pub fn allocate(
+        runtime: Arc<dyn NeuronRuntime>,
+        name: &str,
+        size: usize,
+        logical_nc_id: i32,
+    ) -> Result<Self, NrtError> {
+        let handle = runtime
+            .tensor_allocate(
+                nrt::nrt_tensor_placement_t_NRT_TENSOR_PLACEMENT_DEVICE,
+                logical_nc_id,
+                size,
+                name,
+            )
+            .map_err(|e| e.with_tensor(name))?;
+        Ok(Tensor {
+            runtime,
+            handle,
+            name: name.to_string(),
+            size,
+        })
+    }
+
+    /// The raw handle of the tensor.
+    pub fn handle(&self) -> TensorHandle {
+        self.handle
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The size of the tensor in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Copies `data` into the tensor. `data` must be exactly as large as the tensor.
+    pub fn write_from<T: Element>(&self, data: &[T]) -> Result<(), NrtError> {
+        let bytes = as_bytes(data);
+        self.check_size(bytes.len())?;
+        self.runtime
+            .tensor_write(self.handle, bytes, 0)
+            .map_err(|e| e.with_tensor(&self.name))
+    }
+
+    /// Copies the tensor into `data`. `data` must be exactly as large as the tensor.
+    pub fn read_into<T: Element>(&self, data: &mut [T]) -> Result<(), NrtError> {
+        let bytes = as_bytes_mut(data);
+        self.check_size(bytes.len())?;
+        self.runtime
+            .tensor_read(self.handle, bytes, 0)
+            .map_err(|e| e.with_tensor(&self.name))
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), NrtError> {
+        if size != self.size {
+            return Err(NrtError::SizeMismatch {
+                tensor: self.name.clone(),
+                expected: self.size,
+                actual: size,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Tensor {
+    fn drop(&mut self) {
+        self.runtime.tensor_free(self.handle);
+    }
+}
+
+/// An owned `nrt_tensor_set_t` together with the tensors added to it.
+///
+/// Dropping the set destroys it with `nrt_destroy_tensor_set` and then frees its tensors.
+pub struct TensorSet {
+    runtime: Arc<dyn NeuronRuntime>,
+    handle: TensorSetHandle,
+    tensors: Vec<Tensor>,
+}
+
+impl TensorSet {
+    /// Allocates an empty tensor set.
+    pub fn new(runtime: Arc<dyn NeuronRuntime>) -> Result<Self, NrtError> {
+        let handle = runtime.allocate_tensor_set()?;
+        Ok(TensorSet {
+            runtime,
+            handle,
+            tensors: Vec::new(),
+        })
+    }
+
+    /// Allocates a tensor on the NeuronCore `logical_nc_id` for each entry of
+    /// `info_array` with the given `usage_type` and adds it to a new set under the
+    /// tensor's name. If any allocation fails, the tensors allocated so far are freed.
+    pub fn allocate(
+        runtime: Arc<dyn NeuronRuntime>,
+        info_array: &[TensorInfo],
+        usage_type: nrt::nrt_tensor_usage_t,
+        logical_nc_id: i32,
+    ) -> Result<Self, NrtError> {
+        let mut tensor_set = TensorSet::new(runtime.clone())?;
+        for tensor_info in info_array {
+            if tensor_info.usage != usage_type {
+                continue;
+            }
+            let tensor = Tensor::allocate(
+                runtime.clone(),
+                &tensor_info.name,
+                tensor_info.size,
+                logical_nc_id,
+            )?;
+            tensor_set.add(tensor)?;
+        }
+        Ok(tensor_set)
+    }
+
+    /// Adds `tensor` to the set under its name. The set takes ownership of the tensor.
+    pub fn add(&mut self, tensor: Tensor) -> Result<(), NrtError> {
+        self.runtime
+            .add_tensor_to_tensor_set(self.handle, tensor.name(), tensor.handle())
+            .map_err(|e| e.with_tensor(tensor.name()))?;
+        self.tensors.push(tensor);
+        Ok(())
+    }
+
+    /// Looks up a tensor of the set by name.
+    pub fn get(&self, name: &str) -> Option<&Tensor> {
+        self.tensors.iter().find(|tensor| tensor.name() == name)
+    }
+
+    /// The tensors of the set, in the order they were added.
+    pub fn tensors(&self) -> &[Tensor] {
+        &self.tensors
+    }
+
+    /// The raw handle of the tensor set.
+    pub fn handle(&self) -> TensorSetHandle {
+        self.handle
+    }
+}
+
+impl Drop for TensorSet {
+    fn drop(&mut self) {
+        // The tensors are freed after the set when the fields are dropped.
+        self.runtime.destroy_tensor_set(self.handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::sync::Arc;
+
+    // Local
+    use super::{Tensor, TensorSet};
+    use crate::bindings::nrt;
+    use crate::error::{NrtError, NrtStatus};
+    use crate::fake::FakeRuntime;
+    use crate::runtime::NeuronRuntime;
+    use crate::test_util::f32_tensor;
+
+    #[test]
+    fn tensor_set_round_trips_and_frees_its_tensors() {
+        let runtime = Arc::new(FakeRuntime::new(Vec::new()));
+        runtime.init().unwrap();
+        let info = |name: &str, usage| f32_tensor(name, usage, vec![2]);
+        let info_array = [
+            info("input0", nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT),
+            info("input1", nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT),
+            info("output0", nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT),
+        ];
+
+        let inputs = TensorSet::allocate(
+            runtime.clone(),
+            &info_array,
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
+            0,
+        )
+        .unwrap();
+        assert_eq!(inputs.tensors().len(), 2);
+        assert_eq!(runtime.allocated_tensor_count(), 2);
+
+        let input1 = inputs.get("input1").unwrap();
+        input1.write_from(&[1.5f32, -2.0]).unwrap();
+        let mut data = [0f32; 2];
+        input1.read_into(&mut data).unwrap();
+        assert_eq!(data, [1.5, -2.0]);
+        assert!(matches!(
+            input1.write_from(&[1.0f32]),
+            Err(NrtError::SizeMismatch {
+                expected: 8,
+                actual: 4,
+                ..
+            })
+        ));
+        assert!(inputs.get("output0").is_none());
+
+        drop(inputs);
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+        assert_eq!(runtime.allocated_tensor_set_count(), 0);
+
+        // A tensor that cannot be added to a set is freed.
+        let tensor = Tensor::allocate(runtime.clone(), "input0", 8, 0).unwrap();
+        runtime.fail_next("nrt_add_tensor_to_tensor_set", NrtStatus::Resource);
+        let mut tensor_set = TensorSet::new(runtime.clone()).unwrap();
+        assert!(tensor_set.add(tensor).is_err());
+        drop(tensor_set);
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+        assert_eq!(runtime.allocated_tensor_set_count(), 0);
+
+        // A failed allocation frees the tensors allocated before it.
+        runtime.fail_after("nrt_tensor_allocate", 1, NrtStatus::Resource);
+        assert!(matches!(
+            TensorSet::allocate(
+                runtime.clone(),
+                &info_array,
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
+                0,
+            ),
+            Err(NrtError::Status {
+                status: NrtStatus::Resource,
+                ..
+            })
+        ));
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+        assert_eq!(runtime.allocated_tensor_set_count(), 0);
+    }
+}
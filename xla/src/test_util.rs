@@ -0,0 +1,41 @@
+//! Fixtures shared by the tests of several modules.
+
+// Local
+use crate::bindings::nrt;
+use crate::runtime::TensorInfo;
+
+/// A NEFF the tests load. The fake runtime only reads its bytes.
+pub(crate) const NEFF_PATH: &str = "./transformer_xla_working.neff";
+
+/// The info of an `f32` tensor with the given shape.
+pub(crate) fn f32_tensor(
+    name: &str,
+    usage: nrt::nrt_tensor_usage_t,
+    shape: Vec<u32>,
+) -> TensorInfo {
+    TensorInfo {
+        name: name.to_string(),
+        usage,
+        size: shape.iter().product::<u32>() as usize * std::mem::size_of::<f32>(),
+        dtype: nrt::nrt_dtype_NRT_DTYPE_FLOAT32,
+        shape,
+    }
+}
+
+/// The tensors of a model with an `f32` input `input0` and an `f32` output `output0`,
+/// both of the given shape. The fake runtime's default kernel copies the input to the
+/// output.
+pub(crate) fn echo_model(shape: Vec<u32>) -> Vec<TensorInfo> {
+    vec![
+        f32_tensor(
+            "input0",
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
+            shape.clone(),
+        ),
+        f32_tensor(
+            "output0",
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            shape,
+        ),
+    ]
+}
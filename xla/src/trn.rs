@@ -2,464 +2,314 @@
 //! This is based on the NRT API C Code examples [here](https://awsdocs-neuron.readthedocs-hosted.com/en/latest/neuron-runtime/nrt-api-guide.html#the-code).
 
 // System
-use std::ffi::{CStr, OsStr};
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::os::raw::c_void;
-use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
-use std::ptr::NonNull;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// Third party
+use indexmap::IndexMap;
 
 // Local
 use crate::bindings::nrt;
-use crate::xla_runner::Output;
+use crate::error::{IoOperation, NrtError, NrtStatus};
+use crate::host_tensor::HostTensor;
+use crate::runtime::{NeuronRuntime, TensorInfo};
+use crate::tensor::{Tensor, TensorMut, TensorRef, TensorSet};
 
 /// A function that can be passed to iterate_tensors
 /// to run it once on each tensor.
-pub type TensorHandler = unsafe extern "C" fn(
-    tensor: *mut nrt::nrt_tensor_t,
-    tensor_info: *const nrt::nrt_tensor_info_t,
-    result: *mut nrt::NRT_STATUS,
-    return_value: &mut Output,
-    args: *mut std::ffi::c_void,
-) -> bool;
+pub type TensorHandler = fn(
+    tensor: &Tensor,
+    tensor_info: &TensorInfo,
+    return_value: &mut HostTensor,
+) -> Result<(), NrtError>;
+
+/// Checks that `usage_type` is a valid `nrt_tensor_usage_t` value.
+fn check_usage(call: &'static str, usage_type: nrt::nrt_tensor_usage_t) -> Result<(), NrtError> {
+    if usage_type != nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT
+        && usage_type != nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT
+    {
+        return Err(NrtError::status(call, NrtStatus::Invalid));
+    }
+    Ok(())
+}
 
 /// A wrapper that iterates tensors and calls the given handler on
 /// each tensor.
 ///
-/// # Safety
-///
-/// This function is marked as unsafe due to the use of raw pointers.
-/// To call this function safely, the following invariants must be upheld:
-///
-/// 1. `tset` must be a valid, non-null pointer to an `nrt_tensor_set_t` instance.
-/// 2. `info_array` must be a valid, non-null pointer to an `nrt_tensor_info_array_t` instance,
-///    and the instance should be initialized properly.
-/// 3. `args` can be null or non-null, depending on the requirements of the provided `handler`.
-///
 /// This is based on the code [here](https://awsdocs-neuron.readthedocs-hosted.com/en/latest/neuron-runtime/nrt-api-guide.html#the-code>).
 ///
-/// If these invariants are not upheld, the function may cause undefined behavior or memory corruption.
-/// TODO: This only supports returning f32, but it will need to support other types.
-pub unsafe fn iterate_tensors(
-    tset: *mut nrt::nrt_tensor_set_t,
-    info_array: *mut nrt::nrt_tensor_info_array_t,
+/// Each value is created with the dtype and shape of its tensor; see
+/// [`HostTensor::output_zeros`].
+/// The values are returned by tensor name, in the order of `info_array`, including
+/// zero-sized tensors. It is an error for a tensor to be missing from `tset`.
+pub fn iterate_tensors(
+    tset: &TensorSet,
+    info_array: &[TensorInfo],
     usage_type: nrt::nrt_tensor_usage_t,
     handler: TensorHandler,
-    args: *mut std::ffi::c_void,
-) -> Result<(nrt::NRT_STATUS, Vec<Output>), nrt::NRT_STATUS> {
-    // Check if tset is a non-null pointer
-    if tset.is_null() {
-        eprintln!("Invalid tset pointer");
-        return Err(nrt::NRT_STATUS_NRT_FAILURE);
-    }
-    // Check if info_array is a non-null pointer
-    if info_array.is_null() {
-        eprintln!("Invalid info_array pointer");
-        return Err(nrt::NRT_STATUS_NRT_FAILURE);
-    }
-
-    let mut final_result = nrt::NRT_STATUS_NRT_SUCCESS;
-    let tensor_count = unsafe { (*info_array).tensor_count } as usize;
-    let tensor_info_array = unsafe { (*info_array).tensor_array.as_ptr() };
-
-    let mut return_values: Vec<Output> = Vec::new();
-    for tensor_idx in 0..tensor_count {
-        let tensor_info = unsafe { &*tensor_info_array.add(tensor_idx) };
-
+) -> Result<IndexMap<String, HostTensor>, NrtError> {
+    let mut return_values = IndexMap::new();
+    for tensor_info in info_array {
         if tensor_info.usage != usage_type {
             continue;
         }
 
-        let mut tensor: *mut nrt::nrt_tensor_t = std::ptr::null_mut();
-        let result = unsafe {
-            nrt::nrt_get_tensor_from_tensor_set(tset, tensor_info.name.as_ptr(), &mut tensor)
-        };
+        let tensor = tset.get(&tensor_info.name).ok_or_else(|| {
+            NrtError::status("iterate_tensors", NrtStatus::InvalidHandle)
+                .with_tensor(&tensor_info.name)
+        })?;
 
-        if result != nrt::NRT_STATUS_NRT_SUCCESS {
-            continue;
-        }
-
-        let mut handler_result = nrt::NRT_STATUS_NRT_SUCCESS;
-        let mut return_value = match tensor_info.dtype {
-            nrt::nrt_dtype_NRT_DTYPE_FLOAT32 => Output::Float32(Vec::with_capacity(
-                tensor_info.size / std::mem::size_of::<f32>(),
-            )),
-            nrt::nrt_dtype_NRT_DTYPE_UINT8 => Output::Bool(Vec::with_capacity(tensor_info.size)),
-            _ => panic!("Unsupported dtype {:?}", tensor_info.dtype),
-        };
-        //let mut return_value = Vec::with_capacity(tensor_info.size / std::mem::size_of::<f32>());
-        if !unsafe {
-            handler(
-                tensor,
-                tensor_info as *const _,
-                &mut handler_result,
-                &mut return_value,
-                args,
-            )
-        } {
-            return Err(handler_result);
-        }
-        match return_value {
-            Output::Float32(ref v) => {
-                if !v.is_empty() {
-                    return_values.push(Output::Float32(v.clone()));
-                }
-            }
-            Output::Bool(ref v) => {
-                if !v.is_empty() {
-                    return_values.push(Output::Bool(v.clone()));
-                }
-            }
-        }
-
-        if final_result == nrt::NRT_STATUS_NRT_SUCCESS && handler_result != final_result {
-            final_result = handler_result;
-        }
+        let mut return_value = HostTensor::output_zeros(tensor_info)?;
+        handler(tensor, tensor_info, &mut return_value)?;
+        return_values.insert(tensor_info.name.clone(), return_value);
     }
 
-    Ok((final_result, return_values))
+    Ok(return_values)
 }
 
-/// Save all the output tensors to file
-///
-/// # Safety
-///
-/// This function is marked as unsafe due to the use of raw pointers.
-/// To call this function safely, the following invariants must be upheld:
-///
-/// 1. `tensor` must be a valid, non-null pointer to an `nrt_tensor_t` instance.
-/// 2. `tensor_info` must be a valid, non-null pointer to an `nrt_tensor_info_t` instance,
-///    and the instance should be initialized properly.
-/// 3. `result` must be a valid, non-null pointer to an `nrt::NRT_STATUS` instance.
-/// 4. `_args` is currently unused, so it can be null or non-null.
-///
-/// If these invariants are not upheld, the function may cause undefined behavior or memory corruption.
-pub unsafe extern "C" fn handler_save_outputs(
-    tensor: *mut nrt::nrt_tensor_t,
-    tensor_info: *const nrt::nrt_tensor_info_t,
-    result: *mut nrt::NRT_STATUS,
-    return_value: &mut Output,
-    _args: *mut c_void,
-) -> bool {
-    // Check if tensor is a non-null pointer
-    if tensor.is_null() {
-        eprintln!("Invalid tensor pointer");
-        return false;
-    }
-
-    // Check if tensor_info is a non-null pointer
-    if tensor_info.is_null() {
-        eprintln!("Invalid tensor_info pointer");
-        return false;
-    }
-
-    // Check if result is a non-null pointer
-    if result.is_null() {
-        eprintln!("Invalid result pointer");
-        return false;
-    }
-
-    let tensor_info_name = CStr::from_ptr((*tensor_info).name.as_ptr())
-        .to_str()
-        .unwrap();
-    let tensor_data =
-        std::alloc::alloc(std::alloc::Layout::from_size_align((*tensor_info).size, 1).unwrap())
-            as *mut c_void;
-
-    if tensor_data.is_null() {
-        eprintln!(
-            "Unable to allocate memory for saving output tensor {}",
-            tensor_info_name
-        );
-        *result = nrt::NRT_STATUS_NRT_FAILURE;
-        return true;
-    }
-
-    *result = nrt::nrt_tensor_read(tensor, tensor_data, 0, (*tensor_info).size);
-    if *result != nrt::NRT_STATUS_NRT_SUCCESS {
-        eprintln!("Unable to read tensor {}", tensor_info_name);
-        std::alloc::dealloc(
-            tensor_data as *mut u8,
-            std::alloc::Layout::from_size_align((*tensor_info).size, 1).unwrap(),
-        );
-        return true;
-    }
-
-    let mut filename = PathBuf::from(<OsStr as OsStrExt>::from_bytes(tensor_info_name.as_bytes()));
-    filename.set_extension("out");
+/// Reads each output tensor into memory.
+pub fn handler_read_outputs(
+    tensor: &Tensor,
+    tensor_info: &TensorInfo,
+    return_value: &mut HostTensor,
+) -> Result<(), NrtError> {
+    return_value.check_output_matches(tensor_info)?;
+    tensor.read_into(return_value.as_bytes_mut())
+}
 
-    let mut file = match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&filename)
-    {
-        Ok(file) => file,
-        Err(_) => {
-            eprintln!("Unable to open {} for writing", filename.to_string_lossy());
-            std::alloc::dealloc(
-                tensor_data as *mut u8,
-                std::alloc::Layout::from_size_align((*tensor_info).size, 1).unwrap(),
-            );
-            *result = nrt::NRT_STATUS_NRT_FAILURE;
-            return true;
-        }
+/// Writes the raw bytes of every tensor in `tset` to `<dir>/<tensor name>.out`,
+/// creating `dir` if needed. Path separators in tensor names are replaced by `_`,
+/// so every file stays inside `dir`.
+pub fn dump_tensors(tset: &TensorSet, dir: &Path) -> Result<(), NrtError> {
+    let io_error = |operation, path: &Path, e| NrtError::Io {
+        operation,
+        path: path.to_path_buf(),
+        source: Arc::new(e),
     };
+    std::fs::create_dir_all(dir).map_err(|e| io_error(IoOperation::CreateDir, dir, e))?;
 
-    let write_result = file.write_all(std::slice::from_raw_parts(
-        tensor_data as *const u8,
-        (*tensor_info).size,
-    ));
+    for tensor in tset.tensors() {
+        let mut tensor_data = vec![0u8; tensor.size()];
+        tensor.read_into(&mut tensor_data)?;
 
-    match write_result {
-        Ok(_) => {
-            //println!(
-            //"Wrote tensor {} contents to file {}",
-            //tensor_info_name,
-            //filename.to_string_lossy()
-            //);
-        }
-        Err(_) => {
-            eprintln!(
-                "Unable to write tensor {} contents to file {}",
-                tensor_info_name,
-                filename.to_string_lossy()
-            );
-            *result = nrt::NRT_STATUS_NRT_FAILURE;
-        }
-    }
-    //for i in 0..((*tensor_info).size / std::mem::size_of::<f32>()) {
-    //let value = *(tensor_data as *const f32).add(i);
-    //return_value.push(value);
-    //}
-    match *return_value {
-        Output::Float32(ref mut v) => {
-            if (*tensor_info).dtype == nrt::nrt_dtype_NRT_DTYPE_FLOAT32 {
-                for i in 0..((*tensor_info).size / std::mem::size_of::<f32>()) {
-                    let value = *(tensor_data as *const f32).add(i);
-                    v.push(value);
-                }
-            } else {
-                eprintln!(
-                    "Mismatched data type. Expected f32 but got {:?}",
-                    (*tensor_info).dtype
-                );
-                *result = nrt::NRT_STATUS_NRT_FAILURE;
-                return true;
-            }
-        }
-        Output::Bool(ref mut v) => {
-            if (*tensor_info).dtype == nrt::nrt_dtype_NRT_DTYPE_UINT8 {
-                for i in 0..(*tensor_info).size {
-                    let value = (*(tensor_data as *const u8).add(i)) != 0;
-                    v.push(value);
-                }
-            } else {
-                eprintln!(
-                    "Mismatched data type. Expected bool but got {:?}",
-                    (*tensor_info).dtype
-                );
-                *result = nrt::NRT_STATUS_NRT_FAILURE;
-                return true;
-            }
-        }
+        let filename = dir.join(dump_file_name(tensor.name()));
+        std::fs::write(&filename, &tensor_data)
+            .map_err(|e| io_error(IoOperation::Write, &filename, e))?;
     }
+    Ok(())
+}
 
-    std::alloc::dealloc(
-        tensor_data as *mut u8,
-        std::alloc::Layout::from_size_align((*tensor_info).size, 1).unwrap(),
-    );
-
-    true
+/// Returns the file name a tensor is dumped to, which never names a parent or root directory.
+fn dump_file_name(tensor_name: &str) -> String {
+    format!("{}.out", tensor_name.replace(['/', '\0'], "_"))
 }
 
 /// This is used to load the given values into the input tensors
 /// of the given tensor set.
 ///
-/// # Safety
-///
-/// This function is marked as unsafe due to the use of raw pointers.
-/// To call this function safely, the following invariants must be upheld:
-///
-/// 1. `tensors` must be a valid, non-null pointer to an `nrt_tensor_set_t` instance.
-/// 2. `info_array` must be a valid, non-null pointer to an `nrt_tensor_info_array_t` instance,
-///    and the instance should be initialized properly.
-/// 3. The `tensor_count` and `tensor_array` fields of `info_array` must be correctly initialized.
-/// 4. The `tensor_array` field must point to an array with at least `tensor_count` elements,
-///    and each element must be a properly initialized `nrt_tensor_info`.
-/// 5. The `usage_type` parameter must be a valid `nrt_tensor_usage_t` value.
-/// 6. The `values` Vec should have a length equal to `tensor_count`, and each inner Vec should
-///    have a length that matches the size of the corresponding tensor.
-///
-/// If these invariants are not upheld, the function may cause undefined behavior or memory corruption.
-/// TODO: Generalize this to non-f32 types
-pub fn load_tensor_values(
-    tensors: NonNull<nrt::nrt_tensor_set_t>,
-    info_array: NonNull<nrt::nrt_tensor_info_array_t>,
+/// Each value is bound to the tensor of `info_array` with the given `usage_type` and
+/// the same name, and must have that tensor's dtype and shape. Every such tensor must be
+/// given a value. Nothing is written if any value is rejected.
+pub fn load_tensor_values<I, S>(
+    tensors: &TensorSet,
+    info_array: &[TensorInfo],
     usage_type: nrt::nrt_tensor_usage_t,
-    values: Vec<Vec<f32>>,
-) -> Result<(), nrt::NRT_STATUS> {
-    if values.is_empty() {
-        return Ok(());
+    values: I,
+) -> Result<(), NrtError>
+where
+    I: IntoIterator<Item = (S, HostTensor)>,
+    S: AsRef<str>,
+{
+    check_usage("load_tensor_values", usage_type)?;
+    let bound = bind_values(info_array, usage_type, values, HostTensor::check_matches)?;
+    for (name, value) in bound {
+        write_tensor("load_tensor_values", tensors, name, value.as_bytes())?;
     }
+    Ok(())
+}
 
-    // Check that usage_type is valid
-    if usage_type != nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT
-        && usage_type != nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT
-    {
-        return Err(nrt::NRT_STATUS_NRT_INVALID);
-    }
-
-    // Retrieve tensor_count and tensor_info_array safely
-    let tensor_count = unsafe { info_array.as_ref().tensor_count as usize };
-    let tensor_info_array = unsafe { info_array.as_ref().tensor_array.as_ptr() };
-
-    // Validate the tensor_count and tensor_info_array
-    if tensor_count == 0 {
-        return Err(nrt::NRT_STATUS_NRT_INVALID);
-    }
-
-    let mut num_tensors_loaded = 0;
-
-    for (tensor_idx, data) in values.iter().enumerate() {
-        let tensor_info = unsafe { &*tensor_info_array.add(tensor_idx) };
-
-        let expected_data_length = tensor_info.size / std::mem::size_of::<f32>();
-        if data.len() != expected_data_length {
-            return Err(nrt::NRT_STATUS_NRT_INVALID);
-        }
-
-        if tensor_info.usage != usage_type {
-            continue;
-        }
-
-        let mut tensor: *mut nrt::nrt_tensor_t = std::ptr::null_mut();
-        let result = unsafe {
-            nrt::nrt_get_tensor_from_tensor_set(
-                tensors.as_ptr(),
-                tensor_info.name.as_ptr(),
-                &mut tensor as *mut *mut nrt::nrt_tensor_t,
-            )
-        };
-        if result != nrt::NRT_STATUS_NRT_SUCCESS {
-            return Err(result);
+/// Like [`load_tensor_values`], but writes borrowed bytes straight into the tensors.
+///
+/// Only the size of each value is checked, as the bytes carry no dtype or shape.
+pub fn write_tensor_values(
+    tensors: &TensorSet,
+    info_array: &[TensorInfo],
+    usage_type: nrt::nrt_tensor_usage_t,
+    values: &[TensorRef<'_>],
+) -> Result<(), NrtError> {
+    check_usage("write_tensor_values", usage_type)?;
+    let values = values.iter().map(|value| (value.name, value.bytes));
+    let bound = bind_values(info_array, usage_type, values, |bytes, tensor_info| {
+        if bytes.len() != tensor_info.size {
+            return Err(NrtError::SizeMismatch {
+                tensor: tensor_info.name.clone(),
+                expected: tensor_info.size,
+                actual: bytes.len(),
+            });
         }
+        Ok(())
+    })?;
+    for (name, bytes) in bound {
+        write_tensor("write_tensor_values", tensors, name, bytes)?;
+    }
+    Ok(())
+}
 
-        // Get a pointer to the first element in the Vec
-        let data_ptr = data.as_ptr();
-        // Cast the pointer to a *const c_void
-        let data_c_void_ptr = data_ptr as *const c_void;
-        let tensor_size = data.len() * std::mem::size_of::<f32>();
-
-        let result = unsafe { nrt::nrt_tensor_write(tensor, data_c_void_ptr, 0, tensor_size) };
-        if result != nrt::NRT_STATUS_NRT_SUCCESS {
-            return Err(result);
+/// Reads the tensors of `tset` with the given `usage_type` into caller-owned buffers,
+/// without allocating on the host. Tensors without a buffer are not read.
+pub fn read_tensor_values(
+    tset: &TensorSet,
+    info_array: &[TensorInfo],
+    usage_type: nrt::nrt_tensor_usage_t,
+    buffers: &mut [TensorMut<'_>],
+) -> Result<(), NrtError> {
+    check_usage("read_tensor_values", usage_type)?;
+    let expected = || {
+        info_array
+            .iter()
+            .filter(|tensor_info| tensor_info.usage == usage_type)
+            .map(|tensor_info| tensor_info.name.clone())
+            .collect()
+    };
+    for buffer in buffers.iter() {
+        let known = info_array
+            .iter()
+            .any(|tensor_info| tensor_info.usage == usage_type && tensor_info.name == buffer.name);
+        if !known {
+            return Err(NrtError::UnknownOutput {
+                tensor: buffer.name.to_string(),
+                expected: expected(),
+            });
         }
-        num_tensors_loaded += 1;
     }
-    if num_tensors_loaded != values.len() {
-        let len = values.len();
-        eprintln!(
-            "The number of tensors in the model {tensor_count} does not match the number of values provided {len}"
-        );
-        return Err(nrt::NRT_STATUS_NRT_FAILURE);
+    for buffer in buffers {
+        let tensor = tset.get(buffer.name).ok_or_else(|| {
+            NrtError::status("read_tensor_values", NrtStatus::InvalidHandle)
+                .with_tensor(buffer.name)
+        })?;
+        tensor.read_into(buffer.bytes)?;
     }
     Ok(())
 }
 
-/// Initializes tensor memory in the Trainium hardware.
-///
-/// # Safety
-///
-/// This function is marked as unsafe due to the use of raw pointers.
-/// To call this function safely, the following invariants must be upheld:
-///
-/// 1. `info_array` must be a valid, non-null pointer to an `nrt_tensor_info_array_t` instance,
-///    and the instance should be initialized properly.
-/// 2. The `tensor_count` and `tensor_array` fields of `info_array` must be correctly initialized.
-/// 3. The `tensor_array` field must point to an array with at least `tensor_count` elements,
-///    and each element must be a properly initialized `nrt_tensor_info`.
-/// 4. The `usage_type` parameter must be a valid `nrt_tensor_usage_t` value.
-///
-/// If these invariants are not upheld, the function may cause undefined behavior or memory corruption.
-pub fn allocate_tensors(
-    info_array: NonNull<nrt::nrt_tensor_info_array_t>,
+/// Binds each named value to the tensor of `info_array` with `usage_type` and the same
+/// name, checking it with `check`. Every such tensor must be given exactly one value.
+fn bind_values<I, S, V>(
+    info_array: &[TensorInfo],
     usage_type: nrt::nrt_tensor_usage_t,
-) -> Result<NonNull<nrt::nrt_tensor_set_t>, nrt::NRT_STATUS> {
-    // Check that usage_type is valid
-    if usage_type != nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT
-        && usage_type != nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT
-    {
-        return Err(nrt::NRT_STATUS_NRT_INVALID);
+    values: I,
+    check: impl Fn(&V, &TensorInfo) -> Result<(), NrtError>,
+) -> Result<HashMap<&str, V>, NrtError>
+where
+    I: IntoIterator<Item = (S, V)>,
+    S: AsRef<str>,
+{
+    let expected: Vec<&TensorInfo> = info_array
+        .iter()
+        .filter(|tensor_info| tensor_info.usage == usage_type)
+        .collect();
+    let mut bound: HashMap<&str, V> = HashMap::new();
+    for (name, value) in values {
+        let name = name.as_ref();
+        let tensor_info = expected
+            .iter()
+            .find(|tensor_info| tensor_info.name == name)
+            .ok_or_else(|| NrtError::UnknownInput {
+                tensor: name.to_string(),
+                expected: expected.iter().map(|info| info.name.clone()).collect(),
+            })?;
+        check(&value, tensor_info)?;
+        if bound.insert(&tensor_info.name, value).is_some() {
+            return Err(NrtError::DuplicateInput {
+                tensor: name.to_string(),
+            });
+        }
     }
-
-    let mut out_tset: *mut nrt::nrt_tensor_set_t = std::ptr::null_mut();
-    let result =
-        unsafe { nrt::nrt_allocate_tensor_set(&mut out_tset as *mut *mut nrt::nrt_tensor_set_t) };
-    if result != nrt::NRT_STATUS_NRT_SUCCESS {
-        return Err(result);
+    if let Some(missing) = expected
+        .iter()
+        .find(|tensor_info| !bound.contains_key(tensor_info.name.as_str()))
+    {
+        return Err(NrtError::MissingInput {
+            tensor: missing.name.clone(),
+        });
     }
+    Ok(bound)
+}
 
-    let out_tset = NonNull::new(out_tset).ok_or(nrt::NRT_STATUS_NRT_INVALID)?;
+fn write_tensor(
+    call: &'static str,
+    tensors: &TensorSet,
+    name: &str,
+    bytes: &[u8],
+) -> Result<(), NrtError> {
+    let tensor = tensors
+        .get(name)
+        .ok_or_else(|| NrtError::status(call, NrtStatus::InvalidHandle).with_tensor(name))?;
+    tensor.write_from(bytes)
+}
 
-    // Retrieve tensor_count and tensor_info_array safely
-    let tensor_count = unsafe { info_array.as_ref().tensor_count as usize };
-    let tensor_info_array = unsafe { info_array.as_ref().tensor_array.as_ptr() };
+/// Initializes tensor memory in the Trainium hardware.
+///
+/// A tensor is allocated on the NeuronCore `logical_nc_id` for each entry of
+/// `info_array` with the given `usage_type` and added to a new tensor set under the
+/// tensor's name. If any allocation fails, the tensors allocated so far are freed.
+/// Dropping the returned set frees its tensors.
+pub fn allocate_tensors(
+    runtime: &Arc<dyn NeuronRuntime>,
+    info_array: &[TensorInfo],
+    usage_type: nrt::nrt_tensor_usage_t,
+    logical_nc_id: i32,
+) -> Result<TensorSet, NrtError> {
+    check_usage("allocate_tensors", usage_type)?;
 
     // Validate the tensor_count
-    if tensor_count == 0 {
-        return Err(nrt::NRT_STATUS_NRT_INVALID);
-    }
-
-    for tensor_idx in 0..tensor_count {
-        let tensor_info = unsafe { &*tensor_info_array.add(tensor_idx) };
-
-        if tensor_info.usage != usage_type {
-            continue;
-        }
-
-        let mut tensor: *mut nrt::nrt_tensor_t = std::ptr::null_mut();
-        let result = unsafe {
-            nrt::nrt_tensor_allocate(
-                nrt::nrt_tensor_placement_t_NRT_TENSOR_PLACEMENT_DEVICE,
-                0,
-                tensor_info.size,
-                tensor_info.name.as_ptr(),
-                &mut tensor as *mut *mut nrt::nrt_tensor_t,
-            )
-        };
-
-        if result != nrt::NRT_STATUS_NRT_SUCCESS {
-            return Err(result);
-        }
-
-        let tensor = NonNull::new(tensor).ok_or(nrt::NRT_STATUS_NRT_INVALID)?;
-
-        let result = unsafe {
-            nrt::nrt_add_tensor_to_tensor_set(
-                out_tset.as_ptr(),
-                tensor_info.name.as_ptr(),
-                tensor.as_ptr(),
-            )
-        };
-
-        if result != nrt::NRT_STATUS_NRT_SUCCESS {
-            return Err(result);
-        }
+    if info_array.is_empty() {
+        return Err(NrtError::status("allocate_tensors", NrtStatus::Invalid));
     }
 
-    Ok(out_tset)
+    TensorSet::allocate(runtime.clone(), info_array, usage_type, logical_nc_id)
 }
 
 #[cfg(test)]
 mod tests {
+    // System
+    use std::sync::Arc;
+
     // Local
+    use super::{allocate_tensors, handler_read_outputs, iterate_tensors};
+    use crate::bindings::nrt;
+    use crate::error::NrtError;
+    use crate::fake::FakeRuntime;
+    use crate::runtime::NeuronRuntime;
+    use crate::test_util::f32_tensor;
     use crate::xla_runner::{XLAHardware, XLARunner};
 
     #[test]
+    fn iterate_tensors_fails_on_a_missing_tensor() {
+        let runtime: Arc<dyn NeuronRuntime> = Arc::new(FakeRuntime::new(Vec::new()));
+        runtime.init().unwrap();
+        let info =
+            |name: &str| f32_tensor(name, nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT, vec![1]);
+        let usage = nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT;
+        let outputs = allocate_tensors(&runtime, &[info("output0")], usage, 0).unwrap();
+
+        let result = iterate_tensors(
+            &outputs,
+            &[info("output0"), info("output1")],
+            usage,
+            handler_read_outputs,
+        );
+        match result {
+            Err(NrtError::Status { tensor, .. }) => assert_eq!(tensor.as_deref(), Some("output1")),
+            other => panic!("Expected a missing tensor error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "trn"), ignore = "requires libnrt and a Trainium device")]
     fn transformer_xla_benchmark() {
-        let runner = XLARunner::new(XLAHardware::TRN);
+        let runner = XLARunner::new(XLAHardware::TRN).unwrap();
         let run_name = "transformer_test";
 
         #[allow(unused_variables)]
@@ -474,14 +324,13 @@ mod tests {
 
         runner
             .run_trn(
-                &segfault_path,
-                //&working_path,
+                segfault_path,
+                //working_path,
                 run_name,
                 &input_names,
                 inputs,
                 input_shapes,
             )
             .unwrap();
-        println!("Done");
     }
 }
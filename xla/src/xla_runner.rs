@@ -1,189 +1,1204 @@
 // System
-use std::fs::File;
-use std::io::Read;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Third party
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 
 // Local
 use crate::bindings::nrt;
-use crate::trn::{allocate_tensors, handler_save_outputs, iterate_tensors, load_tensor_values};
+use crate::dtype::TensorData;
+use crate::error::{NrtError, NrtStatus};
+use crate::executor::{Executors, RunFuture};
+use crate::host_tensor::HostTensor;
+use crate::model::{LoadOptions, Model};
+use crate::neff::{estimate_memory, read_neff};
+use crate::runtime::{NeuronRuntime, TensorInfo};
+use crate::shared::{nrt_runtime, RuntimeHandle, SharedRuntime};
+use crate::tensor::{TensorMut, TensorRef, TensorSet};
+use crate::trn::{
+    allocate_tensors, dump_tensors, handler_read_outputs, iterate_tensors, load_tensor_values,
+    read_tensor_values, write_tensor_values,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum XLAHardware {
     TRN,
 }
 
-#[allow(dead_code)]
 pub struct XLARunner {
-    hardware: XLAHardware,
-}
-
-#[derive(Debug, Clone)]
-pub enum Output {
-    Bool(Vec<bool>),
-    Float32(Vec<f32>),
+    runtime: RuntimeHandle,
+    dump_dir: Option<PathBuf>,
+    memory_budget: Option<u64>,
+    executors: Arc<Executors>,
 }
 
 #[derive(Debug, Clone)]
 pub struct XLARunResults {
-    pub output: Vec<Output>,
+    /// The output tensors by name, in the order the model reports them.
+    pub output: IndexMap<String, HostTensor>,
     /// The debug_ir human-readable reprsentation of the XLA HLO
     pub debug_ir: Option<String>,
     /// This is the graph exececution time without any compilation time, tensor allocation time, or
     /// output copying time.
     pub runtime: Duration,
+    /// How long each step of the run took, including the ones `runtime` leaves out.
+    pub timings: RunTimings,
+}
+
+/// How long each step of loading, running and unloading a model took.
+///
+/// A run of a [`LoadedModel`] only times the steps of the run itself; the load steps
+/// are in [`LoadedModel::load_timings`] and `teardown` is zero. [`XLARunner::run_trn`]
+/// times every step.
+///
+/// Durations are serialized as seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunTimings {
+    /// Reading the NEFF file.
+    #[serde(with = "secs_f64")]
+    pub neff_read: Duration,
+    /// `nrt_load`.
+    #[serde(with = "secs_f64")]
+    pub load: Duration,
+    /// `nrt_get_model_tensor_info`.
+    #[serde(with = "secs_f64")]
+    pub tensor_info: Duration,
+    /// Allocating the input tensors.
+    #[serde(with = "secs_f64")]
+    pub input_alloc: Duration,
+    /// Allocating the output tensors.
+    #[serde(with = "secs_f64")]
+    pub output_alloc: Duration,
+    /// Writing the inputs.
+    #[serde(with = "secs_f64")]
+    pub input_write: Duration,
+    /// `nrt_execute`, the same as [`XLARunResults::runtime`].
+    #[serde(with = "secs_f64")]
+    pub execute: Duration,
+    /// Reading the outputs, and dumping them if a dump directory is set.
+    #[serde(with = "secs_f64")]
+    pub output_read: Duration,
+    /// Freeing the tensors and `nrt_unload`.
+    #[serde(with = "secs_f64")]
+    pub teardown: Duration,
+}
+
+impl RunTimings {
+    /// The sum of every step.
+    pub fn total(&self) -> Duration {
+        self.neff_read
+            + self.load
+            + self.tensor_info
+            + self.input_alloc
+            + self.output_alloc
+            + self.input_write
+            + self.execute
+            + self.output_read
+            + self.teardown
+    }
+
+    /// The timings of a run combined with the load steps of its model and the
+    /// teardown after it.
+    pub(crate) fn with_load(self, load: RunTimings, teardown: Duration) -> Self {
+        RunTimings {
+            input_write: self.input_write,
+            execute: self.execute,
+            output_read: self.output_read,
+            teardown,
+            ..load
+        }
+    }
 }
 
 impl XLARunner {
-    /// This inits the Neuron NRT library for Trainium if the trn feature is enabled.
-    /// Note that NRT should be initialized only once per process. If nrt_close is called,
-    /// subsequent calls to nrt_init will fail. This is a known issue with the current version of NRT.
-    pub fn new(hardware: XLAHardware) -> Self {
-        {
-            if hardware == XLAHardware::TRN {
-                // Init NRT
-                let result = unsafe {
-                    nrt::nrt_init(
-                        nrt::nrt_framework_type_t_NRT_FRAMEWORK_TYPE_NO_FW,
-                        std::ptr::null() as *const i8,
-                        std::ptr::null() as *const i8,
-                    )
-                };
-                assert_eq!(result, nrt::NRT_STATUS_NRT_SUCCESS);
-            }
+    /// Creates a runner on the process-wide NRT runtime for Trainium.
+    ///
+    /// Note that NRT can only be initialized once per process: if nrt_close is called,
+    /// subsequent calls to nrt_init will fail. This is a known issue with the current
+    /// version of NRT. NRT is therefore initialized by the first runner, shared by every
+    /// runner after it and only closed when the process exits; dropping a runner does not
+    /// close it. See [`nrt_runtime`] to close it earlier.
+    ///
+    /// A segfault in libnrt kills the whole process. Use
+    /// [`crate::isolated::IsolatedRunner`] to run models in a worker process instead.
+    ///
+    /// libnrt is loaded from the path in the `NRT_LIBRARY_PATH` environment variable, or
+    /// from `libnrt.so.1` on the dynamic linker search path. An `Err` is returned if the
+    /// library or one of its symbols cannot be found.
+    pub fn new(hardware: XLAHardware) -> Result<Self, NrtError> {
+        match hardware {
+            XLAHardware::TRN => Self::with_shared_runtime(&nrt_runtime(None)?),
         }
-        XLARunner { hardware }
     }
 
-    #[allow(unused_variables)]
-    /// This compiles the XLA HLO into a NEFF.
-    /// See [AWS Neuron SDK](https://awsdocs-neuron.readthedocs-hosted.com/en/latest/compiler/neuronx-cc/api-reference-guide/neuron-compiler-cli-reference-guide.html).
+    /// Like [`XLARunner::new`] for Trainium, but loads libnrt from the given path. This
+    /// fails if the process already loaded libnrt from a different path.
+    pub fn with_nrt_library<P: AsRef<OsStr>>(path: P) -> Result<Self, NrtError> {
+        Self::with_shared_runtime(&nrt_runtime(Some(path.as_ref()))?)
+    }
+
+    /// Creates a Trainium runner on top of the given runtime backend, such as
+    /// [`crate::fake::FakeRuntime`], and initializes it. The runtime is not shared with
+    /// other runners; use [`XLARunner::with_shared_runtime`] for that.
+    pub fn with_runtime(runtime: Arc<dyn NeuronRuntime>) -> Result<Self, NrtError> {
+        Self::with_shared_runtime(&SharedRuntime::new(runtime))
+    }
+
+    /// Creates a Trainium runner on a runtime shared with other runners. The runtime is
+    /// initialized if this is the first runner to use it.
+    pub fn with_shared_runtime(runtime: &Arc<SharedRuntime>) -> Result<Self, NrtError> {
+        Ok(XLARunner {
+            runtime: runtime.acquire()?,
+            dump_dir: None,
+            memory_budget: None,
+            executors: runtime.executors(),
+        })
+    }
+
+    /// Sets the directory every model run by this runner writes its raw output tensors
+    /// to, as `<dir>/<tensor name>.out`. Outputs are only returned in memory by default.
+    pub fn set_dump_dir(&mut self, dir: Option<PathBuf>) {
+        self.dump_dir = dir;
+    }
+
+    /// Sets the device memory, in bytes, a model may use on each NeuronCore it is loaded
+    /// onto. A model whose [`estimate_memory`] exceeds the budget of the cores it spans
+    /// is not loaded and [`NrtError::MemoryBudgetExceeded`] is returned instead. There
+    /// is no budget by default.
+    pub fn set_memory_budget(&mut self, bytes: Option<u64>) {
+        self.memory_budget = bytes;
+    }
+
+    /// Sets how many runs [`LoadedModel::run_async`] can queue on each NeuronCore
+    /// before its futures wait for room. The queues are shared by every runner on the
+    /// same [`SharedRuntime`], so this applies to all of them. The default is
+    /// [`crate::executor::DEFAULT_QUEUE_DEPTH`], and a depth of 0 is treated as 1.
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.executors.set_queue_depth(depth);
+    }
+
+    /// The runtime backend this runner executes on.
+    pub fn runtime(&self) -> &Arc<dyn NeuronRuntime> {
+        self.runtime.runtime()
+    }
+
+    /// Runs a NEFF compiled from XLA HLO by `neuronx-cc` on Trainium.
+    ///
+    /// The NEFF is loaded, run once and unloaded. Use [`XLARunner::load`] to run a model
+    /// repeatedly. Each input is given as its name, its values and its shape, which must
+    /// match the shape the model expects.
     pub fn run_trn(
         &self,
         neff_path: &str,
         run_name: &str,
         input_names: &[&str],
-        inputs: Vec<Vec<f32>>,
+        inputs: Vec<TensorData>,
         input_shapes: Vec<Vec<u64>>,
-    ) -> Result<XLARunResults, String> {
+    ) -> Result<XLARunResults, NrtError> {
+        run_once(
+            || self.load_as(Path::new(neff_path), run_name, LoadOptions::default()),
+            input_names,
+            inputs,
+            input_shapes,
+        )
+    }
+
+    /// Like [`XLARunner::run_trn`], but runs the model with
+    /// [`LoadedModel::run_into`], so the inputs are borrowed and the outputs are read
+    /// into the given buffers. Returns how long each step took.
+    pub fn run_trn_into(
+        &self,
+        neff_path: &str,
+        run_name: &str,
+        inputs: &[TensorRef<'_>],
+        outputs: &mut [TensorMut<'_>],
+    ) -> Result<RunTimings, NrtError> {
+        let mut model = self.load_as(Path::new(neff_path), run_name, LoadOptions::default())?;
+
+        // The model is unloaded whether or not the run succeeded.
+        let load_timings = model.load_timings();
+        let result = model.run_into(inputs, outputs);
+        let start = Instant::now();
+        let unloaded = model.unload();
+        let teardown = start.elapsed();
+        let timings = result?;
+        unloaded?;
+        Ok(timings.with_load(load_timings, teardown))
+    }
+
+    /// Loads a NEFF and allocates its input and output tensors, so that it can be run
+    /// many times without reading, loading or allocating anything again.
+    ///
+    /// The model is loaded onto the first NeuronCore. Errors from the model are reported
+    /// with the NEFF path as the model name.
+    pub fn load<P: AsRef<Path>>(&self, neff_path: P) -> Result<LoadedModel, NrtError> {
+        self.load_with_options(neff_path, LoadOptions::default())
+    }
+
+    /// Like [`XLARunner::load`], but loads the model onto the NeuronCores given by
+    /// `options`. Its input and output tensors are allocated on `options.start_nc`.
+    pub fn load_with_options<P: AsRef<Path>>(
+        &self,
+        neff_path: P,
+        options: LoadOptions,
+    ) -> Result<LoadedModel, NrtError> {
+        let neff_path = neff_path.as_ref();
+        self.load_as(neff_path, &neff_path.to_string_lossy(), options)
+    }
+
+    pub(crate) fn load_as(
+        &self,
+        neff_path: &Path,
+        name: &str,
+        options: LoadOptions,
+    ) -> Result<LoadedModel, NrtError> {
+        let start = Instant::now();
+        let neff = read_neff(neff_path)?;
+        let read_time = start.elapsed();
+        if let Some(budget_per_nc) = self.memory_budget {
+            let budget = budget_per_nc.saturating_mul(options.nc_count.max(1) as u64);
+            let estimate = estimate_memory(&neff)?;
+            if estimate.total() > budget {
+                return Err(NrtError::MemoryBudgetExceeded {
+                    model: name.to_string(),
+                    estimate,
+                    budget,
+                });
+            }
+        }
+        let mut model = LoadedModel::load(
+            self.runtime.clone(),
+            self.executors.clone(),
+            &neff,
+            name,
+            options,
+        )
+        .map_err(|e| e.with_model(name))?;
+        model.load_timings.neff_read = read_time;
+        model.set_dump_dir(self.dump_dir.clone());
+        Ok(model)
+    }
+}
+
+/// A model that [`run_once`] can run, such as a [`LoadedModel`].
+pub(crate) trait RunOnce: Sized {
+    fn load_timings(&self) -> RunTimings;
+
+    fn run(&mut self, inputs: HashMap<String, HostTensor>) -> Result<XLARunResults, NrtError>;
+
+    fn unload(self) -> Result<(), NrtError>;
+}
+
+impl RunOnce for LoadedModel {
+    fn load_timings(&self) -> RunTimings {
+        LoadedModel::load_timings(self)
+    }
+
+    fn run(&mut self, inputs: HashMap<String, HostTensor>) -> Result<XLARunResults, NrtError> {
+        LoadedModel::run(self, inputs)
+    }
+
+    fn unload(self) -> Result<(), NrtError> {
+        LoadedModel::unload(self)
+    }
+}
+
+/// Checks the inputs given to `run_trn`, then loads the model with `load`, runs it
+/// once and unloads it, whether or not the run succeeded.
+pub(crate) fn run_once<M: RunOnce>(
+    load: impl FnOnce() -> Result<M, NrtError>,
+    input_names: &[&str],
+    inputs: Vec<TensorData>,
+    input_shapes: Vec<Vec<u64>>,
+) -> Result<XLARunResults, NrtError> {
+    let named_inputs = named_inputs(input_names, inputs, input_shapes)?;
+    let mut model = load()?;
+
+    let load_timings = model.load_timings();
+    let result = model.run(named_inputs);
+    let start = Instant::now();
+    let unloaded = model.unload();
+    let teardown = start.elapsed();
+    let mut results = result?;
+    unloaded?;
+    results.timings = results.timings.with_load(load_timings, teardown);
+    Ok(results)
+}
+
+/// Pairs the names, values and shapes given to `run_trn` into the inputs of a run.
+fn named_inputs(
+    input_names: &[&str],
+    inputs: Vec<TensorData>,
+    input_shapes: Vec<Vec<u64>>,
+) -> Result<HashMap<String, HostTensor>, NrtError> {
+    for count in [inputs.len(), input_shapes.len()] {
+        if input_names.len() != count {
+            return Err(NrtError::InputCountMismatch {
+                expected: input_names.len(),
+                actual: count,
+            });
+        }
+    }
+    let mut named_inputs = HashMap::new();
+    for ((name, data), shape) in input_names.iter().zip(inputs).zip(input_shapes) {
+        let shape = shape.into_iter().map(|dim| dim as usize).collect();
+        if named_inputs
+            .insert(name.to_string(), HostTensor::new(shape, data)?)
+            .is_some()
         {
-            assert_eq!(input_names.len(), inputs.len());
-
-            // Read NEFF file into a byte vector
-            let mut neff_file = File::open(neff_path.clone())
-                .unwrap_or_else(|_| panic!("Unable to open NEFF file {}", neff_path));
-            let mut neff_data: Vec<u8> = Vec::new();
-            neff_file
-                .read_to_end(&mut neff_data)
-                .expect("Unable to read NEFF file");
-            let neff_size = neff_data.len();
-
-            // Load the model
-            let mut model: *mut nrt::nrt_model_t = std::ptr::null_mut();
-            assert_eq!(model, std::ptr::null_mut());
-            assert!(model.is_null());
-            // TODO: In production we will need to set the neuron core ids
-            // based on model sharding.
-            let result = unsafe {
-                nrt::nrt_load(
-                    neff_data.as_ptr() as *const _,
-                    neff_size,
-                    0, // neuron core index to start from
-                    1, // number of neuron cores to allocate the model to
-                    &mut model as *mut *mut nrt::nrt_model_t,
-                )
-            };
-            assert_ne!(model, std::ptr::null_mut());
-            assert!(!model.is_null());
-            assert_eq!(result, nrt::NRT_STATUS_NRT_SUCCESS);
-
-            // Allocate input and ouptut tensors
-            let mut tensor_info_array: *mut nrt::nrt_tensor_info_array_t = std::ptr::null_mut();
-            assert_eq!(tensor_info_array, std::ptr::null_mut());
-            assert!(tensor_info_array.is_null());
-            let result = unsafe {
-                nrt::nrt_get_model_tensor_info(
-                    model,
-                    &mut tensor_info_array as *mut *mut nrt::nrt_tensor_info_array_t,
-                )
-            };
-            assert_eq!(result, nrt::NRT_STATUS_NRT_SUCCESS);
-            let tensor_info_array =
-                std::ptr::NonNull::new(tensor_info_array).expect("Error: null tensor_info_array");
+            return Err(NrtError::DuplicateInput {
+                tensor: name.to_string(),
+            });
+        }
+    }
+    Ok(named_inputs)
+}
 
-            let nrt_inputs = allocate_tensors(
-                tensor_info_array,
-                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
-            );
-            let nrt_inputs = nrt_inputs.expect("Error allocating input tensors");
+/// A model loaded by [`XLARunner::load`] together with its input and output tensors.
+///
+/// The tensors are reused by every run, which must give a value for every input.
+/// Dropping a `LoadedModel` frees its tensors and unloads the model, once any run in progress on
+/// its worker thread has finished.
+pub struct LoadedModel {
+    name: String,
+    dump_dir: Option<PathBuf>,
+    load_timings: RunTimings,
+    executors: Arc<Executors>,
+    state: Arc<ModelState>,
+    /// Signalled whenever a run queued by [`LoadedModel::run_async`] lets go of `state`.
+    released: Arc<(Mutex<()>, Condvar)>,
+}
 
-            let outputs = allocate_tensors(
-                tensor_info_array,
-                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
-            );
-            let outputs = outputs.expect("Error allocating output tensors");
-
-            // Note that even if input parameters are not initialized, it will
-            // still run and it will still produce values.
-            if !inputs.is_empty() {
-                let result = load_tensor_values(
-                    nrt_inputs,
+/// The device side of a [`LoadedModel`], which the runs queued by
+/// [`LoadedModel::run_async`] share.
+struct ModelState {
+    // Fields are dropped in order, so the tensors are freed before the model is
+    // unloaded and the model before the runtime handle is released.
+    inputs: TensorSet,
+    outputs: TensorSet,
+    model: Model,
+    _runtime: RuntimeHandle,
+    /// Held for the whole of a run, so that runs do not overwrite each other's
+    /// tensors. Set once the model is being unloaded.
+    unloading: Mutex<bool>,
+}
+
+impl LoadedModel {
+    fn load(
+        runtime: RuntimeHandle,
+        executors: Arc<Executors>,
+        neff: &[u8],
+        name: &str,
+        options: LoadOptions,
+    ) -> Result<Self, NrtError> {
+        let model = Model::from_bytes_with_options(runtime.runtime().clone(), neff, options)?;
+        let tensor_info_array = model.tensor_info();
+
+        // Allocate input and ouptut tensors
+        let start = Instant::now();
+        let inputs = allocate_tensors(
+            model.runtime(),
+            tensor_info_array,
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
+            options.start_nc,
+        )?;
+        let input_alloc = start.elapsed();
+        let start = Instant::now();
+        let outputs = allocate_tensors(
+            model.runtime(),
+            tensor_info_array,
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            options.start_nc,
+        )?;
+        let output_alloc = start.elapsed();
+        let load_timings = RunTimings {
+            load: model.load_time(),
+            tensor_info: model.tensor_info_time(),
+            input_alloc,
+            output_alloc,
+            ..RunTimings::default()
+        };
+
+        Ok(LoadedModel {
+            name: name.to_string(),
+            dump_dir: None,
+            load_timings,
+            executors,
+            state: Arc::new(ModelState {
+                inputs,
+                outputs,
+                model,
+                _runtime: runtime,
+                unloading: Mutex::new(false),
+            }),
+            released: Arc::new((Mutex::new(()), Condvar::new())),
+        })
+    }
+
+    /// The name errors from this model are reported with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets the directory each run writes its raw output tensors to, as
+    /// `<dir>/<tensor name>.out`. This defaults to the runner's dump directory.
+    pub fn set_dump_dir(&mut self, dir: Option<PathBuf>) {
+        self.dump_dir = dir;
+    }
+
+    /// The underlying model.
+    pub fn model(&self) -> &Model {
+        &self.state.model
+    }
+
+    /// Writes the inputs, executes the model and reads back the outputs.
+    ///
+    /// `inputs` maps the name of every input of the model to its value.
+    pub fn run(&mut self, inputs: HashMap<String, HostTensor>) -> Result<XLARunResults, NrtError> {
+        self.state
+            .run(inputs, self.dump_dir.as_deref())
+            .map_err(|e| e.with_model(&self.name))
+    }
+
+    /// Like [`LoadedModel::run`], but writes the inputs straight from borrowed slices
+    /// and reads the outputs into caller-owned buffers, so no tensor is copied or
+    /// allocated on the host.
+    ///
+    /// The same inputs must be given as for [`LoadedModel::run`], but only their sizes
+    /// are checked. Outputs without a buffer are not read. Returns how long each step
+    /// took.
+    pub fn run_into(
+        &mut self,
+        inputs: &[TensorRef<'_>],
+        outputs: &mut [TensorMut<'_>],
+    ) -> Result<RunTimings, NrtError> {
+        self.state
+            .run_into(inputs, outputs, self.dump_dir.as_deref())
+            .map_err(|e| e.with_model(&self.name))
+    }
+
+    /// Like [`LoadedModel::run`], but runs the model on the worker thread of its
+    /// NeuronCore instead of blocking the calling thread.
+    ///
+    /// Runs of the models loaded onto the same core by the runners on one
+    /// [`SharedRuntime`] are queued and executed one at a time, in the order their
+    /// futures are first polled. See [`RunFuture`]
+    /// for what happens when the queue is full. A run that is still queued when the
+    /// model is dropped fails with [`NrtStatus::InvalidHandle`]. Fails if the worker
+    /// thread cannot be started.
+    pub fn run_async(&self, inputs: HashMap<String, HostTensor>) -> Result<RunFuture, NrtError> {
+        let state = Arc::downgrade(&self.state);
+        let released = self.released.clone();
+        let name = self.name.clone();
+        let dump_dir = self.dump_dir.clone();
+        let logical_nc_id = self.state.model.options().start_nc;
+        self.executors.submit(logical_nc_id, move || {
+            // Dropped after `state`, even if the run panics.
+            let _released = Released(released);
+            let state = state
+                .upgrade()
+                .ok_or_else(|| NrtError::status("nrt_execute", NrtStatus::InvalidHandle))
+                .map_err(|e| e.with_model(&name))?;
+            state
+                .run(inputs, dump_dir.as_deref())
+                .map_err(|e| e.with_model(&name))
+        })
+    }
+
+    /// How long each step of loading the model took. Only the load and allocation
+    /// steps are set.
+    pub fn load_timings(&self) -> RunTimings {
+        self.load_timings
+    }
+
+    /// Frees the tensors and unloads the model now, returning any error from
+    /// `nrt_unload` that dropping the `LoadedModel` would ignore. Waits for a run in
+    /// progress on the worker thread to finish; queued runs fail.
+    pub fn unload(self) -> Result<(), NrtError> {
+        let LoadedModel {
+            name,
+            mut state,
+            released,
+            ..
+        } = self;
+        *lock(&state.unloading) = true;
+        // Queued runs now give up their reference as soon as they take it.
+        let (lock_released, run_released) = &*released;
+        let mut guard = lock(lock_released);
+        let state = loop {
+            match Arc::try_unwrap(state) {
+                Ok(state) => break state,
+                Err(shared) => {
+                    state = shared;
+                    guard = run_released.wait(guard).unwrap_or_else(|e| e.into_inner());
+                }
+            }
+        };
+        drop(guard);
+        let ModelState {
+            inputs,
+            outputs,
+            model,
+            _runtime,
+            ..
+        } = state;
+        drop(inputs);
+        drop(outputs);
+        model.unload().map_err(|e| e.with_model(&name))
+    }
+}
+
+/// Wakes [`LoadedModel::unload`] when a run queued by [`LoadedModel::run_async`] is
+/// dropped, after it has let go of the model.
+struct Released(Arc<(Mutex<()>, Condvar)>);
+
+impl Drop for Released {
+    fn drop(&mut self) {
+        let (lock_released, run_released) = &*self.0;
+        let _guard = lock(lock_released);
+        run_released.notify_all();
+    }
+}
+
+impl ModelState {
+    fn run(
+        &self,
+        inputs: HashMap<String, HostTensor>,
+        dump_dir: Option<&Path>,
+    ) -> Result<XLARunResults, NrtError> {
+        let (output, timings) = self.run_with(
+            dump_dir,
+            |tensors, tensor_info_array| {
+                load_tensor_values(
+                    tensors,
                     tensor_info_array,
                     nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
                     inputs,
-                );
-                result.expect("Error loading input tensor values");
-            }
-
-            // Run it
-            let start = std::time::Instant::now();
-            let result = unsafe { nrt::nrt_execute(model, nrt_inputs.as_ptr(), outputs.as_ptr()) };
-            let runtime = start.elapsed();
-            assert_eq!(
-                result,
-                nrt::NRT_STATUS_NRT_SUCCESS,
-                "nrt_execute failed to run model {}",
-                run_name
-            );
-
-            // TODO: Instead of saving the outputs to file, get them in a Vec<Vec<f32>>
-            // Saving outputs to files
-            let result = unsafe {
+                )
+            },
+            |tensors, tensor_info_array| {
                 iterate_tensors(
-                    outputs.as_ptr(),
-                    tensor_info_array.as_ptr(),
+                    tensors,
+                    tensor_info_array,
                     nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
-                    handler_save_outputs,
-                    std::ptr::null_mut(),
+                    handler_read_outputs,
                 )
-            };
-            let result = result.expect("Error saving output tensors");
-            assert_eq!(result.0, nrt::NRT_STATUS_NRT_SUCCESS);
-            let output = result.1;
-
-            unsafe {
-                nrt::nrt_destroy_tensor_set(&mut nrt_inputs.as_ptr());
-                nrt::nrt_destroy_tensor_set(&mut outputs.as_ptr());
-                nrt::nrt_free_model_tensor_info(tensor_info_array.as_ptr());
-            };
-            //let output = Vec::new();
-            return Ok(XLARunResults {
-                output,
-                debug_ir: None,
-                runtime,
-            });
+            },
+        )?;
+        Ok(XLARunResults {
+            output,
+            debug_ir: None,
+            runtime: timings.execute,
+            timings,
+        })
+    }
+
+    fn run_into(
+        &self,
+        inputs: &[TensorRef<'_>],
+        outputs: &mut [TensorMut<'_>],
+        dump_dir: Option<&Path>,
+    ) -> Result<RunTimings, NrtError> {
+        let ((), timings) = self.run_with(
+            dump_dir,
+            |tensors, tensor_info_array| {
+                write_tensor_values(
+                    tensors,
+                    tensor_info_array,
+                    nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
+                    inputs,
+                )
+            },
+            |tensors, tensor_info_array| {
+                read_tensor_values(
+                    tensors,
+                    tensor_info_array,
+                    nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                    outputs,
+                )
+            },
+        )?;
+        Ok(timings)
+    }
+
+    /// Writes the inputs with `write`, executes the model and reads the outputs with
+    /// `read`, timing each step.
+    fn run_with<T>(
+        &self,
+        dump_dir: Option<&Path>,
+        write: impl FnOnce(&TensorSet, &[TensorInfo]) -> Result<(), NrtError>,
+        read: impl FnOnce(&TensorSet, &[TensorInfo]) -> Result<T, NrtError>,
+    ) -> Result<(T, RunTimings), NrtError> {
+        let unloading = lock(&self.unloading);
+        if *unloading {
+            return Err(NrtError::status("nrt_execute", NrtStatus::InvalidHandle));
+        }
+        let tensor_info_array = self.model.tensor_info();
+
+        // Note that even if input parameters are not initialized, it will
+        // still run and it will still produce values.
+        let start = Instant::now();
+        write(&self.inputs, tensor_info_array)?;
+        let input_write = start.elapsed();
+
+        // Run it
+        let start = Instant::now();
+        self.model.execute(&self.inputs, &self.outputs)?;
+        let execute = start.elapsed();
+
+        let start = Instant::now();
+        let output = read(&self.outputs, tensor_info_array)?;
+        if let Some(dir) = dump_dir {
+            dump_tensors(&self.outputs, dir)?;
         }
-        // This will be seen as unreachable code when --feature trn is enabled
-        #[allow(unreachable_code)]
-        Err("TRN feature is not enabled.".to_string())
+        let output_read = start.elapsed();
+
+        let timings = RunTimings {
+            input_write,
+            execute,
+            output_read,
+            ..RunTimings::default()
+        };
+        Ok((output, timings))
     }
 }
 
-impl Drop for XLARunner {
-    fn drop(&mut self) {
-        if self.hardware == XLAHardware::TRN {
-            unsafe {
-                nrt::nrt_close();
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A run that panicked leaves the tensors valid, just with unknown values.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Serializes a [`Duration`] as fractional seconds.
+pub(crate) mod secs_f64 {
+    // System
+    use std::time::Duration;
+
+    // Third party
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // System
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    // Local
+    use super::XLARunner;
+    use crate::bindings::nrt;
+    use crate::error::{NrtError, NrtStatus};
+    use crate::fake::FakeRuntime;
+    use crate::host_tensor::HostTensor;
+    use crate::model::LoadOptions;
+    use crate::neff::estimate_memory;
+    use crate::runtime::TensorInfo;
+    use crate::shared::SharedRuntime;
+    use crate::tensor::{as_bytes, TensorMut, TensorRef};
+    use crate::test_util::{echo_model, f32_tensor, NEFF_PATH};
+
+    #[test]
+    fn run_trn_on_fake_runtime() {
+        let output_info = |name: &str| {
+            f32_tensor(
+                name,
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                vec![6 * 4096],
+            )
+        };
+        let runtime = Arc::new(FakeRuntime::new(vec![
+            output_info("output1"),
+            output_info("output0"),
+        ]));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+
+        let start = Instant::now();
+        let results = runner
+            .run_trn(NEFF_PATH, "fake_test", &[], vec![], vec![])
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert!(results.output.keys().eq(["output1", "output0"]));
+        for output in results.output.values() {
+            assert_eq!(output.dtype(), nrt::nrt_dtype_NRT_DTYPE_FLOAT32);
+            assert_eq!(output.shape(), [6 * 4096]);
+        }
+        // A one-shot run times loading and unloading too. The steps of the fake can
+        // take less than the clock's resolution, so only their sum is bounded.
+        let timings = results.timings;
+        assert_eq!(timings.execute, results.runtime);
+        assert!(timings.total() <= elapsed);
+        assert_eq!(runtime.loaded_model_count(), 0);
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+        assert_eq!(runtime.allocated_tensor_set_count(), 0);
+    }
+
+    #[test]
+    fn outputs_are_dumped_to_the_dump_dir() {
+        let runtime = Arc::new(FakeRuntime::new(echo_model(vec![2])));
+        let mut runner = XLARunner::with_runtime(runtime).unwrap();
+        let dump_dir = std::env::temp_dir().join(format!("xla_dump_{}", std::process::id()));
+        runner.set_dump_dir(Some(dump_dir.clone()));
+
+        runner
+            .run_trn(
+                NEFF_PATH,
+                "dump_test",
+                &["input0"],
+                vec![vec![1.0f32, 2.0].into()],
+                vec![vec![2]],
+            )
+            .unwrap();
+        let dumped = std::fs::read(dump_dir.join("output0.out")).unwrap();
+        assert_eq!(dumped, [1f32.to_ne_bytes(), 2f32.to_ne_bytes()].concat());
+
+        // Outputs are only returned in memory once the dump directory is unset.
+        std::fs::remove_file(dump_dir.join("output0.out")).unwrap();
+        runner.set_dump_dir(None);
+        runner
+            .run_trn(
+                NEFF_PATH,
+                "dump_test",
+                &["input0"],
+                vec![vec![1.0f32, 2.0].into()],
+                vec![vec![2]],
+            )
+            .unwrap();
+        assert_eq!(std::fs::read_dir(&dump_dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dump_dir).unwrap();
+
+        // Tensor names cannot leave the dump directory or collide on their extension.
+        let output =
+            |name: &str| f32_tensor(name, nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT, vec![1]);
+        let runtime = Arc::new(FakeRuntime::new(vec![
+            output("../escape"),
+            output("/abs"),
+            output("a.x"),
+            output("a.y"),
+        ]));
+        let mut runner = XLARunner::with_runtime(runtime).unwrap();
+        let parent = std::env::temp_dir().join(format!("xla_dump_names_{}", std::process::id()));
+        let dump_dir = parent.join("dump");
+        runner.set_dump_dir(Some(dump_dir.clone()));
+        runner
+            .run_trn(NEFF_PATH, "dump_names", &[], vec![], vec![])
+            .unwrap();
+        let mut files: Vec<_> = std::fs::read_dir(&dump_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, [".._escape.out", "_abs.out", "a.x.out", "a.y.out"]);
+        assert_eq!(std::fs::read_dir(&parent).unwrap().count(), 1);
+        std::fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn run_into_uses_borrowed_inputs_and_output_buffers() {
+        let runtime = Arc::new(FakeRuntime::new(echo_model(vec![4])));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        let mut model = runner.load(NEFF_PATH).unwrap();
+
+        let input = [1f32, 2.0, 3.0, 4.0];
+        let mut output = vec![0f32; 4];
+        let start = Instant::now();
+        let timings = model
+            .run_into(
+                &[TensorRef::new("input0", &input)],
+                &mut [TensorMut::new("output0", &mut output)],
+            )
+            .unwrap();
+        assert_eq!(output, input);
+        assert!(timings.total() <= start.elapsed());
+        assert_eq!(timings.neff_read, Duration::ZERO);
+
+        // Raw bytes are accepted for any dtype, but must be the size of the tensor.
+        let input = [5f32, 6.0, 7.0, 8.0];
+        let bytes = as_bytes(&input);
+        model
+            .run_into(
+                &[TensorRef::new("input0", bytes)],
+                &mut [TensorMut::new("output0", &mut output)],
+            )
+            .unwrap();
+        assert_eq!(output, input);
+        assert!(matches!(
+            model.run_into(&[TensorRef::new("input0", &bytes[..8])], &mut []),
+            Err(NrtError::SizeMismatch {
+                expected: 16,
+                actual: 8,
+                ..
+            })
+        ));
+        assert!(matches!(
+            model.run_into(
+                &[TensorRef::new("input0", bytes)],
+                &mut [TensorMut::new("output1", &mut output)]
+            ),
+            Err(NrtError::UnknownOutput { .. })
+        ));
+        assert!(matches!(
+            model.run_into(&[], &mut [TensorMut::new("output0", &mut output)]),
+            Err(NrtError::MissingInput { .. })
+        ));
+        drop(model);
+
+        let start = Instant::now();
+        let timings = runner
+            .run_trn_into(
+                NEFF_PATH,
+                "borrowed",
+                &[TensorRef::new("input0", &input)],
+                &mut [TensorMut::new("output0", &mut output)],
+            )
+            .unwrap();
+        assert!(timings.total() <= start.elapsed());
+        assert_eq!(runtime.loaded_model_count(), 0);
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+    }
+
+    #[test]
+    fn loaded_model_reuses_model_and_tensors_across_runs() {
+        let runtime = Arc::new(FakeRuntime::new(echo_model(vec![4])));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+
+        let start = Instant::now();
+        let mut model = runner.load(NEFF_PATH).unwrap();
+        assert!(model.load_timings().total() <= start.elapsed());
+        assert_eq!(model.load_timings().execute, Duration::ZERO);
+        assert_eq!(model.name(), NEFF_PATH);
+        assert_eq!(runtime.loaded_model_count(), 1);
+        assert_eq!(runtime.allocated_tensor_count(), 2);
+        for i in 0..3 {
+            let input = vec![i as f32, 1.0, 2.0, 3.0];
+            let inputs =
+                HashMap::from([("input0".to_string(), HostTensor::from_vec(input.clone()))]);
+            let results = model.run(inputs).unwrap();
+            assert_eq!(results.output["output0"], HostTensor::from_vec(input));
+            assert_eq!(runtime.loaded_model_count(), 1);
+            assert_eq!(runtime.allocated_tensor_count(), 2);
+            assert_eq!(runtime.allocated_tensor_set_count(), 2);
+            // Runs of a loaded model leave the load steps to its load timings.
+            assert_eq!(results.timings.neff_read, Duration::ZERO);
+        }
+
+        // Every input must be given on every run.
+        assert!(matches!(
+            model.run(HashMap::new()),
+            Err(NrtError::MissingInput { tensor }) if tensor == "input0"
+        ));
+
+        runtime.fail_next("nrt_execute", NrtStatus::Timeout);
+        let inputs = HashMap::from([("input0".to_string(), HostTensor::from_vec(vec![0f32; 4]))]);
+        match model.run(inputs) {
+            Err(NrtError::Status { model: name, .. }) => {
+                assert_eq!(name.as_deref(), Some(NEFF_PATH))
             }
+            other => panic!("Expected nrt_execute to fail, got {:?}", other),
         }
+
+        drop(model);
+        assert_eq!(runtime.loaded_model_count(), 0);
+        assert_eq!(runtime.allocated_tensor_count(), 0);
+        assert_eq!(runtime.allocated_tensor_set_count(), 0);
+    }
+
+    #[test]
+    fn runners_share_one_initialized_runtime() {
+        let fake = Arc::new(FakeRuntime::new(vec![f32_tensor(
+            "output0",
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            vec![4],
+        )]));
+        let shared = SharedRuntime::new(fake.clone());
+
+        let first = XLARunner::with_shared_runtime(&shared).unwrap();
+        let second = XLARunner::with_shared_runtime(&shared).unwrap();
+        drop(first);
+        assert!(fake.is_initialized());
+        second
+            .run_trn(NEFF_PATH, "shared_test", &[], vec![], vec![])
+            .unwrap();
+    }
+
+    #[test]
+    fn run_trn_reports_and_cleans_up_every_failure() {
+        let runtime = Arc::new(FakeRuntime::new(echo_model(vec![4])));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        let run = |neff_path: &str| {
+            runner.run_trn(
+                neff_path,
+                "fault_test",
+                &["input0"],
+                vec![vec![1.0f32, 2.0, 3.0, 4.0].into()],
+                vec![vec![4]],
+            )
+        };
+
+        match run("./missing.neff") {
+            Err(NrtError::Io { path, .. }) => assert_eq!(path.to_str(), Some("./missing.neff")),
+            other => panic!("Expected an Io error, got {:?}", other),
+        }
+
+        for call in [
+            "nrt_load",
+            "nrt_get_model_tensor_info",
+            "nrt_allocate_tensor_set",
+            "nrt_tensor_allocate",
+            "nrt_add_tensor_to_tensor_set",
+            "nrt_tensor_write",
+            "nrt_execute",
+            "nrt_tensor_read",
+            "nrt_unload",
+        ] {
+            runtime.fail_next(call, NrtStatus::Resource);
+            match run(NEFF_PATH) {
+                Err(NrtError::Status {
+                    status,
+                    call: failed_call,
+                    model,
+                    ..
+                }) => {
+                    assert_eq!(status, NrtStatus::Resource);
+                    assert_eq!(failed_call, call);
+                    assert_eq!(model.as_deref(), Some("fault_test"));
+                }
+                other => panic!("Expected {} to fail, got {:?}", call, other),
+            }
+            assert_eq!(runtime.allocated_tensor_count(), 0, "{}", call);
+            assert_eq!(runtime.allocated_tensor_set_count(), 0, "{}", call);
+            if call != "nrt_unload" {
+                assert_eq!(runtime.loaded_model_count(), 0, "{}", call);
+            }
+        }
+
+        assert!(matches!(
+            runner.run_trn(
+                NEFF_PATH,
+                "fault_test",
+                &[],
+                vec![vec![1.0f32].into()],
+                vec![]
+            ),
+            Err(NrtError::InputCountMismatch { .. })
+        ));
+        assert!(matches!(
+            runner.run_trn(
+                NEFF_PATH,
+                "fault_test",
+                &["input0"],
+                vec![vec![1.0f32].into()],
+                vec![vec![1]]
+            ),
+            Err(NrtError::ShapeMismatch { .. })
+        ));
+        assert!(matches!(
+            runner.run_trn(
+                NEFF_PATH,
+                "fault_test",
+                &["input0"],
+                vec![vec![1.0f32; 4].into()],
+                vec![vec![2]]
+            ),
+            Err(NrtError::InvalidShape { .. })
+        ));
+        assert!(matches!(
+            runner.run_trn(
+                NEFF_PATH,
+                "fault_test",
+                &["input0"],
+                vec![vec![1i32, 2, 3, 4].into()],
+                vec![vec![4]]
+            ),
+            Err(NrtError::DtypeMismatch {
+                expected: nrt::nrt_dtype_NRT_DTYPE_FLOAT32,
+                actual: nrt::nrt_dtype_NRT_DTYPE_INT32,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn models_over_the_memory_budget_are_not_loaded() {
+        let runtime = Arc::new(FakeRuntime::new(vec![f32_tensor(
+            "output0",
+            nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+            vec![4],
+        )]));
+        let mut runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        let needed = estimate_memory(&std::fs::read(NEFF_PATH).unwrap())
+            .unwrap()
+            .total();
+
+        runner.set_memory_budget(Some(needed - 1));
+        match runner.load(NEFF_PATH) {
+            Err(NrtError::MemoryBudgetExceeded {
+                model,
+                estimate,
+                budget,
+            }) => {
+                assert_eq!(model, NEFF_PATH);
+                assert_eq!(estimate.total(), needed);
+                assert_eq!(budget, needed - 1);
+            }
+            other => panic!("Expected the budget to be exceeded, got {:?}", other.err()),
+        }
+        assert_eq!(runtime.loaded_model_count(), 0);
+
+        runner.set_memory_budget(Some(needed));
+        assert!(runner.load(NEFF_PATH).is_ok());
+
+        // The budget is per NeuronCore.
+        runner.set_memory_budget(Some(needed / 2 + 1));
+        let two_cores = LoadOptions {
+            start_nc: 0,
+            nc_count: 2,
+        };
+        assert!(runner.load_with_options(NEFF_PATH, two_cores).is_ok());
+    }
+
+    #[test]
+    fn models_and_tensors_are_placed_on_the_requested_cores() {
+        let runtime = Arc::new(FakeRuntime::new(echo_model(vec![4])));
+        let runner = XLARunner::with_runtime(runtime.clone()).unwrap();
+        let sharded = LoadOptions {
+            start_nc: 2,
+            nc_count: 4,
+        };
+
+        let first = runner.load(NEFF_PATH).unwrap();
+        let second = runner.load_with_options(NEFF_PATH, sharded).unwrap();
+        assert_eq!(first.model().options(), LoadOptions::default());
+        assert_eq!(second.model().options(), sharded);
+        assert_eq!(
+            runtime.loaded_model_placements(),
+            [LoadOptions::default(), sharded]
+        );
+        assert_eq!(runtime.allocated_tensor_ncs(), [0, 0, 2, 2]);
+
+        let invalid = LoadOptions {
+            start_nc: 0,
+            nc_count: 0,
+        };
+        assert!(runner.load_with_options(NEFF_PATH, invalid).is_err());
+    }
+
+    #[test]
+    fn inputs_are_bound_by_name() {
+        let runtime = Arc::new(FakeRuntime::new(vec![
+            f32_tensor(
+                "output0",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                vec![3],
+            ),
+            f32_tensor("b", nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT, vec![1]),
+            f32_tensor("a", nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT, vec![2]),
+        ]));
+        let runner = XLARunner::with_runtime(runtime).unwrap();
+        let run = |names: &[&str], inputs: Vec<Vec<f32>>| {
+            let shapes = inputs.iter().map(|v| vec![v.len() as u64]).collect();
+            let inputs = inputs.into_iter().map(Into::into).collect();
+            runner.run_trn(NEFF_PATH, "name_test", names, inputs, shapes)
+        };
+
+        // The fake concatenates the inputs in the order of the model's tensor info.
+        let results = run(&["a", "b"], vec![vec![1.0, 2.0], vec![3.0]]).unwrap();
+        assert_eq!(
+            results.output["output0"],
+            HostTensor::from_vec(vec![3f32, 1.0, 2.0])
+        );
+
+        match run(&["a"], vec![vec![1.0, 2.0]]) {
+            Err(NrtError::MissingInput { tensor }) => assert_eq!(tensor, "b"),
+            other => panic!("Expected a missing input, got {:?}", other),
+        }
+        match run(&["a", "b", "c"], vec![vec![1.0, 2.0], vec![3.0], vec![4.0]]) {
+            Err(NrtError::UnknownInput { tensor, expected }) => {
+                assert_eq!(tensor, "c");
+                assert_eq!(expected, ["b", "a"]);
+            }
+            other => panic!("Expected an unknown input, got {:?}", other),
+        }
+        assert!(matches!(
+            run(&["b", "b"], vec![vec![3.0], vec![3.0]]),
+            Err(NrtError::DuplicateInput { .. })
+        ));
+    }
+
+    #[test]
+    fn outputs_are_read_with_their_dtype() {
+        let tensor = |name: &str, usage, dtype, size, shape| TensorInfo {
+            name: name.to_string(),
+            usage,
+            size,
+            dtype,
+            shape,
+        };
+        let runtime = Arc::new(FakeRuntime::new(vec![
+            tensor(
+                "token_ids",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_INPUT,
+                nrt::nrt_dtype_NRT_DTYPE_INT64,
+                16,
+                vec![2],
+            ),
+            tensor(
+                "positions",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                nrt::nrt_dtype_NRT_DTYPE_INT64,
+                16,
+                vec![2],
+            ),
+            tensor(
+                "logits",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                nrt::nrt_dtype_NRT_DTYPE_BFLOAT16,
+                8,
+                vec![2, 2],
+            ),
+            tensor(
+                "empty",
+                nrt::nrt_tensor_usage_NRT_TENSOR_USAGE_OUTPUT,
+                nrt::nrt_dtype_NRT_DTYPE_FLOAT32,
+                0,
+                vec![0, 4],
+            ),
+        ]));
+        let runner = XLARunner::with_runtime(runtime).unwrap();
+
+        let results = runner
+            .run_trn(
+                NEFF_PATH,
+                "dtype_test",
+                &["token_ids"],
+                vec![vec![7i64, -1].into()],
+                vec![vec![2]],
+            )
+            .unwrap();
+        assert!(results.output.keys().eq(["positions", "logits", "empty"]));
+        assert_eq!(
+            results.output["positions"],
+            HostTensor::from_vec(vec![7i64, -1])
+        );
+        assert_eq!(
+            results.output["logits"].dtype(),
+            nrt::nrt_dtype_NRT_DTYPE_BFLOAT16
+        );
+        assert_eq!(results.output["logits"].shape(), [2, 2]);
+        // Zero-sized outputs are kept.
+        assert_eq!(results.output["empty"].shape(), [0, 4]);
     }
 }